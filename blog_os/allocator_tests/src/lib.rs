@@ -0,0 +1,49 @@
+// The heap allocators of the multitasking kernel, built for the host.
+//
+// The allocator modules only depend on a few items of their parent module (`align_up`, `Locked`,
+// and `grow_heap`), which are provided here without any kernel dependencies.
+
+// the kernel code follows the style of the blog posts, which these lints disagree with
+#![allow(
+    clippy::new_without_default,
+    clippy::missing_safety_doc,
+    clippy::needless_borrow
+)]
+
+extern crate alloc;
+
+use std::sync::{Mutex, MutexGuard};
+
+#[path = "../../multitasking/src/allocator/bump.rs"]
+pub mod bump;
+#[path = "../../multitasking/src/allocator/linked_list.rs"]
+pub mod linked_list;
+#[path = "../../multitasking/src/allocator/fixed_size_block.rs"]
+pub mod fixed_size_block;
+
+/// Host version of the kernel's `allocator::Locked`, based on `std::sync::Mutex`.
+pub struct Locked<A> {
+    inner: Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock().unwrap()
+    }
+}
+
+/// Align the given address `addr` upwards to alignment `align`.
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// The heap cannot grow on the host, the tests use a fixed byte array.
+fn grow_heap(_heap_top: usize, _min_size: usize) -> usize {
+    0
+}
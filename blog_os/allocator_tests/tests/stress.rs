@@ -0,0 +1,225 @@
+// Randomized alloc/free/realloc stress tests for the kernel heap allocators, running on the host.
+//
+// Every allocation is filled with a pattern that is checked again before it is freed or after it is reallocated,
+// so overlapping allocations are detected even if the overlap check itself were wrong.
+
+use allocator_tests::{
+    bump::BumpAllocator, fixed_size_block::FixedSizeBlockAllocator,
+    linked_list::LinkedListAllocator, Locked,
+};
+use std::alloc::{alloc, dealloc, GlobalAlloc, Layout};
+
+const HEAP_SIZE: usize = 1024 * 1024;
+const OPERATIONS: usize = 20_000;
+// all live allocations are freed after this many operations, which is the only way for the bump allocator to reuse memory
+const PHASE_LENGTH: usize = 1000;
+const MAX_LIVE: usize = 200;
+
+/// A page-aligned byte array that serves as the heap of one allocator.
+struct HeapMemory {
+    start: *mut u8,
+}
+
+impl HeapMemory {
+    fn layout() -> Layout {
+        Layout::from_size_align(HEAP_SIZE, 4096).unwrap()
+    }
+
+    fn new() -> Self {
+        let start = unsafe { alloc(Self::layout()) };
+        assert!(!start.is_null());
+        HeapMemory { start }
+    }
+
+    fn start(&self) -> usize {
+        self.start as usize
+    }
+
+    fn contains(&self, addr: usize, size: usize) -> bool {
+        addr >= self.start() && addr + size <= self.start() + HEAP_SIZE
+    }
+}
+
+impl Drop for HeapMemory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.start, Self::layout()) };
+    }
+}
+
+/// A xorshift generator, so that a failing run can be reproduced from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    fill: u8,
+}
+
+impl Live {
+    fn check(&self, len: usize) {
+        let data = unsafe { std::slice::from_raw_parts(self.ptr, len) };
+        assert!(
+            data.iter().all(|&b| b == self.fill),
+            "allocation at {:p} ({:?}) was overwritten",
+            self.ptr,
+            self.layout
+        );
+    }
+}
+
+fn random_layout(rng: &mut Rng) -> Layout {
+    // mostly small allocations, with an occasional large one that goes to the fallback allocator
+    let size = match rng.below(10) {
+        0 => 2048 + rng.below(8192),
+        1..=3 => 1 + rng.below(512),
+        _ => 1 + rng.below(64),
+    };
+    let align = 1 << rng.below(9);
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// Checks a new allocation and adds it to `live`.
+fn add_live(heap: &HeapMemory, live: &mut Vec<Live>, ptr: *mut u8, layout: Layout, fill: u8) {
+    let addr = ptr as usize;
+    assert_eq!(addr % layout.align(), 0, "{:?} is misaligned at {:p}", layout, ptr);
+    assert!(heap.contains(addr, layout.size()), "{:p} is outside of the heap", ptr);
+    for other in live.iter() {
+        let other_addr = other.ptr as usize;
+        assert!(
+            addr + layout.size() <= other_addr || other_addr + other.layout.size() <= addr,
+            "{:p} ({:?}) overlaps {:p} ({:?})",
+            ptr,
+            layout,
+            other.ptr,
+            other.layout
+        );
+    }
+    unsafe { ptr.write_bytes(fill, layout.size()) };
+    live.push(Live { ptr, layout, fill });
+}
+
+/// Runs a random sequence of allocations, deallocations and reallocations and frees everything at the end.
+///
+/// Returns the number of allocations that failed because the heap was exhausted.
+fn stress(allocator: &impl GlobalAlloc, heap: &HeapMemory, seed: u64) -> usize {
+    let mut rng = Rng(seed);
+    let mut live: Vec<Live> = Vec::new();
+    let mut failed = 0;
+
+    for operation in 0..OPERATIONS {
+        let fill = operation as u8;
+        match rng.below(4) {
+            // allocate
+            0 | 1 if live.len() < MAX_LIVE => {
+                let layout = random_layout(&mut rng);
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    failed += 1;
+                } else {
+                    add_live(heap, &mut live, ptr, layout, fill);
+                }
+            }
+            // reallocate
+            2 if !live.is_empty() => {
+                let old = live.swap_remove(rng.below(live.len()));
+                old.check(old.layout.size());
+                let new_size = random_layout(&mut rng).size();
+                let ptr = unsafe { allocator.realloc(old.ptr, old.layout, new_size) };
+                if ptr.is_null() {
+                    // the old allocation is still valid
+                    failed += 1;
+                    live.push(old);
+                } else {
+                    let moved = Live { ptr, ..old };
+                    moved.check(old.layout.size().min(new_size));
+                    let layout = Layout::from_size_align(new_size, old.layout.align()).unwrap();
+                    add_live(heap, &mut live, ptr, layout, fill);
+                }
+            }
+            // free
+            _ if !live.is_empty() => {
+                let old = live.swap_remove(rng.below(live.len()));
+                old.check(old.layout.size());
+                unsafe { allocator.dealloc(old.ptr, old.layout) };
+            }
+            _ => {}
+        }
+
+        if operation % PHASE_LENGTH == PHASE_LENGTH - 1 {
+            free_all(allocator, &mut live);
+        }
+    }
+    free_all(allocator, &mut live);
+    failed
+}
+
+fn free_all(allocator: &impl GlobalAlloc, live: &mut Vec<Live>) {
+    for old in live.drain(..) {
+        old.check(old.layout.size());
+        unsafe { allocator.dealloc(old.ptr, old.layout) };
+    }
+}
+
+/// Asserts that the whole heap can be allocated in one piece, i.e. that all freed memory was recovered.
+fn assert_heap_recovered(allocator: &impl GlobalAlloc, heap: &HeapMemory) {
+    let layout = Layout::from_size_align(HEAP_SIZE, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize, heap.start(), "free space was not fully recovered");
+    unsafe { allocator.dealloc(ptr, layout) };
+}
+
+#[test]
+fn bump_allocator() {
+    let heap = HeapMemory::new();
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(heap.start(), HEAP_SIZE) };
+
+    for seed in 1..=4 {
+        // the bump allocator only reuses memory at the end of a phase, so running out of memory is expected
+        stress(&allocator, &heap, seed);
+        assert_heap_recovered(&allocator, &heap);
+    }
+}
+
+#[test]
+fn linked_list_allocator() {
+    let heap = HeapMemory::new();
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(heap.start(), HEAP_SIZE) };
+
+    for seed in 1..=4 {
+        assert_eq!(stress(&allocator, &heap, seed), 0);
+        assert_heap_recovered(&allocator, &heap);
+    }
+}
+
+#[test]
+fn fixed_size_block_allocator() {
+    let heap = HeapMemory::new();
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(heap.start(), HEAP_SIZE) };
+
+    for seed in 1..=4 {
+        assert_eq!(stress(&allocator, &heap, seed), 0);
+        // freed blocks stay in their lists instead of going back to the fallback allocator, but no block may be lost
+        let size_classes = allocator.lock().size_classes();
+        assert!(size_classes.iter().all(|class| class.in_use == 0));
+    }
+
+    // releasing the free lists, as done when the kernel heap is exhausted, recovers the whole heap
+    allocator.lock().release_free_blocks();
+    assert_heap_recovered(&allocator, &heap);
+}
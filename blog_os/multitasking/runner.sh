@@ -0,0 +1,37 @@
+#!/bin/sh
+# Cargo runner (see .cargo/config.toml): embeds the kernel symbol table into the `.ksyms` section of the kernel
+# (see src/symbols.rs) and then boots the kernel with `bootimage runner`.
+#
+# usage: runner.sh <kernel> [bootimage runner arguments]
+set -e
+
+kernel="$1"
+NM="${NM:-nm}"
+OBJCOPY="${OBJCOPY:-objcopy}"
+
+table=$(mktemp)
+trap 'rm -f "$table"' EXIT
+
+# functions (text symbols) as "<address> <size> <name>", sorted by address; the hash of legacy Rust names is removed
+"$NM" --defined-only --demangle --numeric-sort --print-size "$kernel" |
+    sed -n -E \
+        -e 's/^([0-9a-f]{16}) ([0-9a-f]{16}) [tTwW] (.*)$/\1 \2 \3/p' \
+        -e 's/^([0-9a-f]{16}) [tTwW] (.*)$/\1 0000000000000000 \2/p' |
+    sed -E 's/::h[0-9a-f]{16}$//' > "$table"
+
+section_size=$(size -A "$kernel" | awk '$1 == ".ksyms" { print $2 }')
+if [ -z "$section_size" ]; then
+    echo "runner.sh: $kernel has no .ksyms section" >&2
+    exit 1
+fi
+table_size=$(wc -c < "$table")
+if [ "$table_size" -ge "$section_size" ]; then
+    echo "runner.sh: symbol table ($table_size bytes) does not fit into .ksyms ($section_size bytes)," \
+        "increase SYMBOL_TABLE_SIZE in src/symbols.rs" >&2
+    exit 1
+fi
+# the section keeps its size, so that no address changes
+truncate -s "$section_size" "$table"
+"$OBJCOPY" --update-section .ksyms="$table" "$kernel"
+
+exec bootimage runner "$@"
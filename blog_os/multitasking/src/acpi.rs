@@ -0,0 +1,293 @@
+// ACPI tables
+//
+// The firmware describes the platform (interrupt controllers, timers, power management) in the ACPI tables. They are
+// found through the root system description pointer (RSDP), which points to the root table (RSDT, or XSDT from ACPI 2.0),
+// which in turn lists the physical addresses of all other tables.
+//
+// All tables are read through the mapping of the complete physical memory that the bootloader sets up (see `memory::init`).
+// `init` parses the tables that the kernel knows once; other subsystems query them through `tables`.
+
+use conquer_once::spin::OnceCell;
+use core::{mem, ptr, slice};
+use x86_64::{PhysAddr, VirtAddr};
+
+// multiple APIC description table, describes the interrupt controllers
+pub mod madt;
+// fixed ACPI description table, describes the power management hardware
+pub mod fadt;
+// HPET description table
+pub mod hpet;
+// PCI express memory-mapped configuration space
+pub mod mcfg;
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::Madt;
+pub use mcfg::Mcfg;
+
+/// An error while looking for or parsing an ACPI table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The RSDP was not found in the BIOS memory areas.
+    RsdpNotFound,
+    /// The checksum of the table with the given signature is wrong.
+    InvalidChecksum([u8; 4]),
+    /// The root table does not list a table with the given signature.
+    TableNotFound([u8; 4]),
+}
+
+/// Root system description pointer, ACPI 1.0 part
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+}
+
+/// Root system description pointer, ACPI 2.0 extension
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp2 {
+    rsdp: Rsdp,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The header that every system description table starts with.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// A register location as described by ACPI (generic address structure).
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+}
+
+/// The tables that `init` found and parsed. Tables that the firmware does not provide are `None`.
+pub struct AcpiTables {
+    /// ACPI revision of the RSDP (0 for ACPI 1.0, 2 for ACPI 2.0 and later).
+    pub revision: u8,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>,
+    /// Access to the raw tables, e.g. the DSDT.
+    pub acpi: Acpi,
+}
+
+static TABLES: OnceCell<AcpiTables> = OnceCell::uninit();
+
+/// Finds and parses the ACPI tables. Afterwards, `tables` returns them.
+///
+/// Only fails if the RSDP or the root table cannot be found or are invalid. A missing or invalid MADT, FADT, HPET or
+/// MCFG table is `None`, and the other tables are still usable.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that the complete physical memory is mapped to virtual
+/// memory at the passed `physical_memory_offset`. Also, this function must be only called once, since the parsed
+/// tables keep reading physical memory through the `physical_memory_offset` of the first call.
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> Result<&'static AcpiTables, AcpiError> {
+    if let Ok(tables) = TABLES.try_get() {
+        return Ok(tables);
+    }
+    let acpi = Acpi::new(physical_memory_offset)?;
+    let parsed = AcpiTables {
+        revision: acpi.revision,
+        madt: acpi.madt().ok(),
+        fadt: acpi.fadt().ok(),
+        hpet: acpi.hpet().ok(),
+        mcfg: acpi.mcfg().ok(),
+        acpi,
+    };
+    Ok(TABLES.get_or_init(|| parsed))
+}
+
+/// Returns the tables parsed by `init`, or `None` if `init` was not called or failed.
+pub fn tables() -> Option<&'static AcpiTables> {
+    TABLES.try_get().ok()
+}
+
+/// Access to the ACPI tables.
+pub struct Acpi {
+    physical_memory_offset: VirtAddr,
+    revision: u8,
+    // the RSDT (32-bit entries) or XSDT (64-bit entries)
+    root_table: PhysAddr,
+    root_entry_size: usize,
+}
+
+impl Acpi {
+    /// Finds the RSDP and validates the root table.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the complete physical memory is mapped to virtual
+    /// memory at the passed `physical_memory_offset`. Also, this function must be only called once, which `init`
+    /// already does: the tables are then accessed through `tables`.
+    pub unsafe fn new(physical_memory_offset: VirtAddr) -> Result<Acpi, AcpiError> {
+        let mut acpi = Acpi {
+            physical_memory_offset,
+            revision: 0,
+            root_table: PhysAddr::new(0),
+            root_entry_size: 0,
+        };
+        let rsdp_address = acpi.find_rsdp().ok_or(AcpiError::RsdpNotFound)?;
+        let rsdp: Rsdp = acpi.read(rsdp_address);
+        acpi.revision = rsdp.revision;
+        if rsdp.revision >= 2 {
+            // the extended checksum covers the whole ACPI 2.0 structure
+            let rsdp2: Rsdp2 = acpi.read(rsdp_address);
+            if acpi.checksum(rsdp_address, rsdp2.length as usize) != 0 {
+                return Err(AcpiError::InvalidChecksum(*b"RSD "));
+            }
+            acpi.root_table = PhysAddr::new(rsdp2.xsdt_address);
+            acpi.root_entry_size = mem::size_of::<u64>();
+        } else {
+            acpi.root_table = PhysAddr::new(u64::from(rsdp.rsdt_address));
+            acpi.root_entry_size = mem::size_of::<u32>();
+        }
+        acpi.table(acpi.root_table)?;
+        Ok(acpi)
+    }
+
+    /// Returns the complete table at the given physical address, including its header, after validating its checksum.
+    pub fn table(&self, address: PhysAddr) -> Result<&'static [u8], AcpiError> {
+        unsafe {
+            let header: SdtHeader = self.read(address);
+            if self.checksum(address, header.length as usize) != 0 {
+                return Err(AcpiError::InvalidChecksum(header.signature));
+            }
+            let start = self.physical_memory_offset + address.as_u64();
+            Ok(slice::from_raw_parts(start.as_ptr(), header.length as usize))
+        }
+    }
+
+    /// Returns the physical address of the first table with the given signature and a valid checksum.
+    ///
+    /// Tables with an invalid checksum are skipped. If all tables with the signature are invalid, the error of the first
+    /// one is returned.
+    pub fn find_table(&self, signature: &[u8; 4]) -> Result<PhysAddr, AcpiError> {
+        let mut error = AcpiError::TableNotFound(*signature);
+        let root: SdtHeader = unsafe { self.read(self.root_table) };
+        let entries = (root.length as usize - mem::size_of::<SdtHeader>()) / self.root_entry_size;
+        let entries_start = self.root_table + mem::size_of::<SdtHeader>();
+        for i in 0..entries {
+            let entry = entries_start + i * self.root_entry_size;
+            let address = unsafe {
+                match self.root_entry_size {
+                    4 => u64::from(self.read::<u32>(entry)),
+                    _ => self.read::<u64>(entry),
+                }
+            };
+            let header: SdtHeader = unsafe { self.read(PhysAddr::new(address)) };
+            if &header.signature == signature {
+                match self.table(PhysAddr::new(address)) {
+                    Ok(_) => return Ok(PhysAddr::new(address)),
+                    Err(err) => {
+                        if let AcpiError::TableNotFound(_) = error {
+                            error = err;
+                        }
+                    }
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Parses the MADT.
+    pub fn madt(&self) -> Result<Madt, AcpiError> {
+        let address = self.find_table(&Madt::SIGNATURE)?;
+        Ok(unsafe { Madt::parse(self, address) })
+    }
+
+    /// Parses the FADT.
+    pub fn fadt(&self) -> Result<Fadt, AcpiError> {
+        let address = self.find_table(&Fadt::SIGNATURE)?;
+        Ok(unsafe { Fadt::parse(self, address) })
+    }
+
+    /// Parses the HPET description table.
+    pub fn hpet(&self) -> Result<Hpet, AcpiError> {
+        let address = self.find_table(&Hpet::SIGNATURE)?;
+        Ok(unsafe { Hpet::parse(self, address) })
+    }
+
+    /// Parses the MCFG.
+    pub fn mcfg(&self) -> Result<Mcfg, AcpiError> {
+        let address = self.find_table(&Mcfg::SIGNATURE)?;
+        Ok(unsafe { Mcfg::parse(self, address) })
+    }
+
+    /// Reads a value from physical memory.
+    ///
+    /// This function is unsafe because the caller must guarantee that a `T` is stored at `address`.
+    /// The value does not need to be aligned.
+    unsafe fn read<T: Copy>(&self, address: PhysAddr) -> T {
+        let virt = self.physical_memory_offset + address.as_u64();
+        virt.as_ptr::<T>().read_unaligned()
+    }
+
+    /// Reads a table that starts with the header and is described by `T`.
+    ///
+    /// Older revisions of a table can be shorter than `T`. The fields behind the end of the table are zero.
+    /// This function is unsafe because the caller must guarantee that `T` only consists of integers.
+    unsafe fn read_table<T: Copy>(&self, address: PhysAddr) -> T {
+        let header: SdtHeader = self.read(address);
+        let len = (header.length as usize).min(mem::size_of::<T>());
+        let mut table: T = mem::zeroed();
+        let src = (self.physical_memory_offset + address.as_u64()).as_ptr::<u8>();
+        ptr::copy_nonoverlapping(src, &mut table as *mut T as *mut u8, len);
+        table
+    }
+
+    /// Searches the RSDP in the first KiB of the extended BIOS data area and in the BIOS ROM.
+    ///
+    /// The RSDP is aligned to 16 bytes and starts with the signature "RSD PTR ". Only an RSDP with a valid checksum is accepted.
+    unsafe fn find_rsdp(&self) -> Option<PhysAddr> {
+        // the real-mode segment of the extended BIOS data area is stored at 0x40e
+        let ebda = u64::from(self.read::<u16>(PhysAddr::new(0x40e))) << 4;
+        let areas = [(ebda, ebda + 1024), (0xe0000, 0x100000)];
+        for &(start, end) in areas.iter() {
+            for address in (start..end).step_by(16) {
+                let address = PhysAddr::new(address);
+                let rsdp: Rsdp = self.read(address);
+                if &rsdp.signature == b"RSD PTR " && self.checksum(address, mem::size_of::<Rsdp>()) == 0 {
+                    return Some(address);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the sum of the bytes in the given range, which is zero for a valid ACPI structure.
+    unsafe fn checksum(&self, address: PhysAddr, len: usize) -> u8 {
+        (0..len).fold(0u8, |sum, offset| sum.wrapping_add(self.read::<u8>(address + offset)))
+    }
+}
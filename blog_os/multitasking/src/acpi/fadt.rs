@@ -0,0 +1,144 @@
+use super::{Acpi, GenericAddress, SdtHeader};
+use x86_64::PhysAddr;
+
+/// The raw FADT up to the extended PM1 control blocks.
+///
+/// ACPI 1.0 tables end after `flags`, so the later fields are only valid if the table is long enough.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct FadtFields {
+    header: SdtHeader,
+    firmware_ctrl: u32,
+    dsdt: u32,
+    reserved0: u8,
+    preferred_pm_profile: u8,
+    sci_interrupt: u16,
+    smi_command_port: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_control: u8,
+    pm1a_event_block: u32,
+    pm1b_event_block: u32,
+    pm1a_control_block: u32,
+    pm1b_control_block: u32,
+    pm2_control_block: u32,
+    pm_timer_block: u32,
+    gpe0_block: u32,
+    gpe1_block: u32,
+    pm1_event_length: u8,
+    pm1_control_length: u8,
+    pm2_control_length: u8,
+    pm_timer_length: u8,
+    gpe0_length: u8,
+    gpe1_length: u8,
+    gpe1_base: u8,
+    cstate_control: u8,
+    worst_c2_latency: u16,
+    worst_c3_latency: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alarm: u8,
+    month_alarm: u8,
+    century: u8,
+    boot_architecture_flags: u16,
+    reserved1: u8,
+    flags: u32,
+    // ACPI 2.0
+    reset_register: GenericAddress,
+    reset_value: u8,
+    arm_boot_architecture_flags: u16,
+    minor_version: u8,
+    x_firmware_ctrl: u64,
+    x_dsdt: u64,
+    x_pm1a_event_block: GenericAddress,
+    x_pm1b_event_block: GenericAddress,
+    x_pm1a_control_block: GenericAddress,
+    x_pm1b_control_block: GenericAddress,
+}
+
+/// Fixed ACPI description table
+#[derive(Debug, Clone)]
+pub struct Fadt {
+    pub revision: u8,
+    /// Physical address of the differentiated system description table, which contains the AML code of the platform.
+    pub dsdt: PhysAddr,
+    /// The GSI of the system control interrupt.
+    pub sci_interrupt: u16,
+    /// The I/O port through which ACPI mode is entered or left, 0 if the system is always in ACPI mode.
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    /// I/O port of the PM1a control register, which puts the system into a sleep state.
+    pub pm1a_control_block: u32,
+    /// I/O port of the PM1b control register, 0 if there is none.
+    pub pm1b_control_block: u32,
+    /// I/O port of the ACPI power management timer, 0 if there is none.
+    pub pm_timer_block: u32,
+    /// Index of the century register in the CMOS RTC, 0 if there is none.
+    pub century: u8,
+    pub boot_architecture_flags: u16,
+    pub flags: u32,
+    /// The register that resets the system when `reset_value` is written to it (ACPI 2.0).
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    pub const SIGNATURE: [u8; 4] = *b"FACP";
+
+    /// The system has a keyboard controller (8042) at ports 0x60 and 0x64.
+    pub const BOOT_ARCH_8042: u16 = 1 << 1;
+    /// The reset register is supported.
+    pub const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+    /// Parses the FADT at the given physical address.
+    ///
+    /// This function is unsafe because the caller must guarantee that a FADT is stored at `address`.
+    pub(super) unsafe fn parse(acpi: &Acpi, address: PhysAddr) -> Fadt {
+        let fields: FadtFields = acpi.read_table(address);
+        // the extended fields replace the 32-bit fields if they are present (i.e. not zero)
+        let dsdt = match fields.x_dsdt {
+            0 => u64::from(fields.dsdt),
+            x_dsdt => x_dsdt,
+        };
+        let pm1a_control_block = io_port(fields.x_pm1a_control_block).unwrap_or(fields.pm1a_control_block);
+        let pm1b_control_block = io_port(fields.x_pm1b_control_block).unwrap_or(fields.pm1b_control_block);
+        let reset_supported = fields.flags & Fadt::FLAG_RESET_REG_SUP != 0 && fields.reset_register.address != 0;
+        Fadt {
+            revision: fields.header.revision,
+            dsdt: PhysAddr::new(dsdt),
+            sci_interrupt: fields.sci_interrupt,
+            smi_command_port: fields.smi_command_port,
+            acpi_enable: fields.acpi_enable,
+            acpi_disable: fields.acpi_disable,
+            pm1a_control_block,
+            pm1b_control_block,
+            pm_timer_block: fields.pm_timer_block,
+            century: fields.century,
+            boot_architecture_flags: fields.boot_architecture_flags,
+            flags: fields.flags,
+            reset_register: if reset_supported { Some(fields.reset_register) } else { None },
+            reset_value: fields.reset_value,
+        }
+    }
+
+    /// Returns whether the system has an 8042 keyboard controller.
+    ///
+    /// ACPI 1.0 has no boot architecture flags, so the controller is assumed to exist on those systems.
+    pub fn has_8042(&self) -> bool {
+        self.revision < 2 || self.boot_architecture_flags & Fadt::BOOT_ARCH_8042 != 0
+    }
+}
+
+/// Returns the I/O port of an extended register block, or `None` if it is not used or not in the I/O space.
+fn io_port(register: GenericAddress) -> Option<u32> {
+    let address = register.address;
+    if register.address_space == GenericAddress::SYSTEM_IO && address != 0 {
+        Some(address as u32)
+    } else {
+        None
+    }
+}
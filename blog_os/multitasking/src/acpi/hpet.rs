@@ -0,0 +1,55 @@
+use super::{Acpi, GenericAddress, SdtHeader};
+use x86_64::PhysAddr;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct HpetFields {
+    header: SdtHeader,
+    event_timer_block_id: u32,
+    base_address: GenericAddress,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+/// High precision event timer description table
+#[derive(Debug, Clone)]
+pub struct Hpet {
+    /// Physical address of the memory-mapped HPET registers.
+    pub base_address: PhysAddr,
+    pub hardware_revision: u8,
+    /// Number of comparators (timers) of the HPET.
+    pub comparators: u8,
+    /// The main counter is 64 bits wide.
+    pub counter_64bit: bool,
+    /// The HPET can replace the PIT and the RTC interrupts (legacy replacement routing).
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+    /// Number of the HPET, if there are several.
+    pub number: u8,
+    /// The minimum number of counter ticks for a periodic timer that does not lose interrupts.
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    pub const SIGNATURE: [u8; 4] = *b"HPET";
+
+    /// Parses the HPET description table at the given physical address.
+    ///
+    /// This function is unsafe because the caller must guarantee that an HPET description table is stored at `address`.
+    pub(super) unsafe fn parse(acpi: &Acpi, address: PhysAddr) -> Hpet {
+        let fields: HpetFields = acpi.read_table(address);
+        let id = fields.event_timer_block_id;
+        Hpet {
+            base_address: PhysAddr::new(fields.base_address.address),
+            hardware_revision: id as u8,
+            // bits 8-12 are the number of the last comparator
+            comparators: ((id >> 8) & 0x1f) as u8 + 1,
+            counter_64bit: id & (1 << 13) != 0,
+            legacy_replacement: id & (1 << 15) != 0,
+            pci_vendor_id: (id >> 16) as u16,
+            number: fields.hpet_number,
+            minimum_tick: fields.minimum_tick,
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use super::{Acpi, SdtHeader};
+use alloc::vec::Vec;
+use core::mem;
+use x86_64::PhysAddr;
+
+/// The MADT part that follows the header, before the variable-length entries.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct MadtFields {
+    local_apic_address: u32,
+    flags: u32,
+}
+
+/// A processor and its local APIC (entry type 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicEntry {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// Whether the processor can be used.
+    pub enabled: bool,
+}
+
+/// An I/O APIC (entry type 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first global system interrupt (GSI) that the I/O APIC handles.
+    pub gsi_base: u32,
+}
+
+/// A legacy ISA interrupt that is not identity-mapped to a GSI or not edge-triggered and active high (entry type 2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+/// How an interrupt line signals an interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal {
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+/// Multiple APIC description table
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// The system also has the two 8259 PICs, which need to be masked when the APIC is used.
+    pub has_8259_pics: bool,
+    pub local_apics: Vec<LocalApicEntry>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptSourceOverride>,
+}
+
+impl Madt {
+    pub const SIGNATURE: [u8; 4] = *b"APIC";
+
+    /// Parses the MADT at the given physical address.
+    ///
+    /// This function is unsafe because the caller must guarantee that a MADT is stored at `address`.
+    pub(super) unsafe fn parse(acpi: &Acpi, address: PhysAddr) -> Madt {
+        let header: SdtHeader = acpi.read(address);
+        let fields: MadtFields = acpi.read(address + mem::size_of::<SdtHeader>());
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(u64::from(fields.local_apic_address)),
+            has_8259_pics: fields.flags & 1 != 0,
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        // every entry starts with its type and length
+        let end = address + header.length as u64;
+        let mut entry = address + mem::size_of::<SdtHeader>() + mem::size_of::<MadtFields>();
+        while entry + 2u64 <= end {
+            let entry_type: u8 = acpi.read(entry);
+            let len: u8 = acpi.read(entry + 1u64);
+            if len < 2 {
+                break;
+            }
+            match entry_type {
+                0 => madt.local_apics.push(LocalApicEntry {
+                    processor_id: acpi.read(entry + 2u64),
+                    apic_id: acpi.read(entry + 3u64),
+                    enabled: acpi.read::<u32>(entry + 4u64) & 1 != 0,
+                }),
+                1 => madt.io_apics.push(IoApicEntry {
+                    id: acpi.read(entry + 2u64),
+                    address: PhysAddr::new(u64::from(acpi.read::<u32>(entry + 4u64))),
+                    gsi_base: acpi.read(entry + 8u64),
+                }),
+                2 => madt.overrides.push(InterruptSourceOverride {
+                    irq: acpi.read(entry + 3u64),
+                    gsi: acpi.read(entry + 4u64),
+                    flags: acpi.read(entry + 8u64),
+                }),
+                // 64-bit local APIC address override
+                5 => madt.local_apic_address = PhysAddr::new(acpi.read(entry + 4u64)),
+                _ => {}
+            }
+            entry += u64::from(len);
+        }
+        madt
+    }
+
+    /// Returns the GSI and the signal of the given legacy ISA interrupt.
+    pub fn isa_irq(&self, irq: u8) -> (u32, Signal) {
+        // ISA interrupts are edge-triggered and active high, unless an override says otherwise
+        let mut gsi = u32::from(irq);
+        let mut signal = Signal {
+            active_low: false,
+            level_triggered: false,
+        };
+        if let Some(over) = self.overrides.iter().find(|over| over.irq == irq) {
+            gsi = over.gsi;
+            // bits 0-1 are the polarity and bits 2-3 the trigger mode, 0 means "conforming to the bus"
+            signal.active_low = over.flags & 0b11 == 0b11;
+            signal.level_triggered = (over.flags >> 2) & 0b11 == 0b11;
+        }
+        (gsi, signal)
+    }
+}
@@ -0,0 +1,70 @@
+use super::{Acpi, SdtHeader};
+use alloc::vec::Vec;
+use core::mem;
+use x86_64::PhysAddr;
+
+/// The size of the reserved field between the header and the entries.
+const RESERVED: usize = 8;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct McfgEntry {
+    base_address: u64,
+    segment_group: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32,
+}
+
+/// The memory-mapped configuration space of a range of PCI buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigSpace {
+    /// Physical address of the configuration space of bus 0 (even if `start_bus` is higher).
+    pub base_address: PhysAddr,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// PCI express memory-mapped configuration space description table
+#[derive(Debug, Clone)]
+pub struct Mcfg {
+    pub entries: Vec<ConfigSpace>,
+}
+
+impl Mcfg {
+    pub const SIGNATURE: [u8; 4] = *b"MCFG";
+
+    /// Parses the MCFG at the given physical address.
+    ///
+    /// This function is unsafe because the caller must guarantee that a MCFG is stored at `address`.
+    pub(super) unsafe fn parse(acpi: &Acpi, address: PhysAddr) -> Mcfg {
+        let header: SdtHeader = acpi.read(address);
+        let start = mem::size_of::<SdtHeader>() + RESERVED;
+        let count = (header.length as usize).saturating_sub(start) / mem::size_of::<McfgEntry>();
+        let entries = (0..count)
+            .map(|i| {
+                let entry: McfgEntry = acpi.read(address + start + i * mem::size_of::<McfgEntry>());
+                ConfigSpace {
+                    base_address: PhysAddr::new(entry.base_address),
+                    segment_group: entry.segment_group,
+                    start_bus: entry.start_bus,
+                    end_bus: entry.end_bus,
+                }
+            })
+            .collect();
+        Mcfg { entries }
+    }
+
+    /// Returns the physical address of the configuration space of the given PCI function.
+    ///
+    /// Every function has 4 KiB of configuration space. Returns `None` if no entry covers the bus.
+    pub fn config_address(&self, segment_group: u16, bus: u8, device: u8, function: u8) -> Option<PhysAddr> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.segment_group == segment_group && (entry.start_bus..=entry.end_bus).contains(&bus))?;
+        let offset = u64::from(bus) << 20 | u64::from(device & 0x1f) << 15 | u64::from(function & 0x7) << 12;
+        Some(entry.base_address + offset)
+    }
+}
@@ -1,6 +1,25 @@
-// use bump::BumpAllocator;
-// use linked_list::LinkedListAllocator;
-use fixed_size_block::FixedSizeBlockAllocator;
+// The global allocator is picked at build time through exactly one of the `alloc-*` cargo features (`alloc-fixed-block` by default), e.g.
+// `cargo test --no-default-features --features alloc-bump`. Every allocator provides a const `new` and an `init(heap_start, heap_size)` method.
+#[cfg(feature = "alloc-bump")]
+type GlobalAllocator = bump::BumpAllocator;
+#[cfg(feature = "alloc-linked-list")]
+type GlobalAllocator = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-fixed-block")]
+type GlobalAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-fixed-block",
+)))]
+compile_error!("no global allocator selected, enable one of the `alloc-*` features");
+
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-linked-list"),
+    all(feature = "alloc-bump", feature = "alloc-fixed-block"),
+    all(feature = "alloc-linked-list", feature = "alloc-fixed-block"),
+))]
+compile_error!("only one `alloc-*` feature can be enabled, use `--no-default-features` to disable `alloc-fixed-block`");
 
 // bump allocator
 pub mod bump;
@@ -8,6 +27,19 @@ pub mod bump;
 pub mod linked_list;
 // fixed-size block allocator
 pub mod fixed_size_block;
+// slab caches for fixed-size kernel objects
+pub mod slab;
+// arenas that free all of their allocations at once
+pub mod arena;
+// heap usage statistics
+pub mod stats;
+// opt-in tracking of live allocations for leak checks in tests
+pub mod leak;
+// handlers that free memory when the heap is exhausted
+pub mod oom;
+// red zones and poisoning to detect heap corruption
+#[cfg(feature = "alloc-debug")]
+pub mod debug;
 
 /// creating a kernel heap
 /// 
@@ -16,14 +48,118 @@ pub mod fixed_size_block;
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE:  usize = 100 * 1024; // 100 KiB
 
+/// growing the kernel heap
+///
+/// `HEAP_SIZE` is only the size that is mapped at boot. When the heap is exhausted, the allocator maps more pages directly above the current heap end,
+/// at least `HEAP_GROW_SIZE` bytes at a time, until the heap reaches the limit set through `set_heap_limit` (`HEAP_MAX_SIZE` by default).
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+pub const HEAP_GROW_SIZE: usize = 64 * 1024; // 64 KiB
+
+const PAGE_SIZE: usize = 4096;
+
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+/// Sets the maximum size that the heap may grow to.
+///
+/// Lowering the limit below the current heap size does not shrink the heap, it only prevents further growth.
+pub fn set_heap_limit(size: usize) {
+    HEAP_LIMIT.store(size, Ordering::Relaxed);
+}
+
 // The #[global_allocator] attribute tells the Rust compiler which allocator instance it should use as the global heap allocator.
 // The attribute is only applicable to a static that implements the GlobalAlloc trait.
 #[global_allocator]
 // The struct is named LockedHeap because it uses the spinning_top::Spinlock type for synchronization. This is required because multiple threads could access the ALLOCATOR static at the same time.
 // As always, when using a spinlock or a mutex, we need to be careful to not accidentally cause a deadlock. This means that we shouldn’t perform any allocations in interrupt handlers, since they can run at an arbitrary time and might interrupt an in-progress allocation.
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = 
-    Locked::new(FixedSizeBlockAllocator::new());
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    allocator: Locked::new(GlobalAllocator::new()),
+};
+
+/// The global allocator: the allocator selected through the `alloc-*` features, plus the bookkeeping that is shared by all of them.
+///
+/// The wrapper forwards to the selected allocator and records every allocation in the `stats` counters.
+/// With the `alloc-debug` feature, the allocations additionally go through the red zone checks of the `debug` module.
+/// Between `leak_check_begin` and `leak_check_end`, every allocation is also recorded by the `leak` tracker.
+/// When an allocation fails, the wrapper frees cached memory (see `reclaim`) and retries once.
+pub struct KernelAllocator {
+    allocator: Locked<GlobalAllocator>,
+}
+
+impl KernelAllocator {
+    unsafe fn alloc_inner(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "alloc-debug")]
+        return debug::alloc(&self.allocator, layout);
+        #[cfg(not(feature = "alloc-debug"))]
+        return self.allocator.alloc(layout);
+    }
+
+    /// Frees cached memory after an allocation with the given layout failed and returns the number of bytes that can be
+    /// allocated again.
+    ///
+    /// The handlers registered through `oom::register_oom_handler` are called first. Then the allocator's own caches are
+    /// emptied: the quarantine of the `alloc-debug` mode, which also holds the memory that the handlers freed, and the
+    /// free lists of the `FixedSizeBlockAllocator`, which also hold the blocks that left the quarantine.
+    fn reclaim(&self, layout: Layout) -> usize {
+        #[cfg(not(feature = "alloc-debug"))]
+        let freed = oom::run_handlers(layout);
+        #[cfg(feature = "alloc-debug")]
+        let freed = {
+            // the memory freed by the handlers is in the quarantine, so it only counts once the quarantine is flushed
+            oom::run_handlers(layout);
+            unsafe { debug::flush_quarantine(&self.allocator) }
+        };
+        #[cfg(feature = "alloc-fixed-block")]
+        let freed = freed + self.allocator.lock().release_free_blocks();
+        freed
+    }
+}
 
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = self.alloc_inner(layout);
+        if ptr.is_null() && self.reclaim(layout) > 0 {
+            ptr = self.alloc_inner(layout);
+        }
+        stats::record_alloc(layout, ptr);
+        leak::record_alloc(ptr, layout);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "alloc-debug")]
+        debug::dealloc(&self.allocator, ptr, layout);
+        #[cfg(not(feature = "alloc-debug"))]
+        self.allocator.dealloc(ptr, layout);
+        stats::record_dealloc(layout);
+        leak::record_dealloc(ptr);
+    }
+}
+
+pub use leak::{leak_check_begin, leak_check_end};
+pub use oom::{register_oom_handler, OomHandler};
+
+/// Returns a snapshot of the current heap usage.
+///
+/// The size class and fallback counters are only filled in when the `FixedSizeBlockAllocator` is the global allocator.
+pub fn stats() -> HeapStats {
+    #[allow(unused_mut)]
+    let mut stats = stats::snapshot();
+    #[cfg(feature = "alloc-fixed-block")]
+    {
+        let allocator = ALLOCATOR.allocator.lock();
+        stats.size_classes = allocator.size_classes();
+        stats.fallback_allocations = allocator.fallback_allocations();
+    }
+    stats
+}
+
+use crate::memory;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+use stats::HeapStats;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
@@ -55,31 +191,73 @@ pub fn init_heap(
     // 2. Mapping the pages
     // map all pages of the page range we just created. For that, we iterate over these pages using a for loop.
     for page in page_range {
-        // allocate a physical frame that the page should be mapped to using the FrameAllocator::allocate_frame method. 
-        // This method returns None when there are no more frames left. We deal with that case by mapping it to a MapToError::FrameAllocationFailed error through the Option::ok_or method and then applying the question mark operator to return early in the case of an error.
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        // set the required PRESENT flag and the WRITABLE flag for the page. 
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            // use the Mapper::map_to method for creating the mapping in the active page table.
-            // The method can fail, so we use "?" again to forward the error to the caller.
-            // On success, the method returns a MapperFlush instance that we can use to update the translation lookaside buffer using the flush method.
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush()
-        };
+        map_heap_page(page, mapper, frame_allocator)?;
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.allocator.lock().init(HEAP_START, HEAP_SIZE);
     }
+    stats::record_heap_growth(HEAP_SIZE);
 
     Ok(())
 }
 
+/// Maps the given heap page to a newly allocated frame.
+fn map_heap_page(
+    page: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    // allocate a physical frame that the page should be mapped to using the FrameAllocator::allocate_frame method. 
+    // This method returns None when there are no more frames left. We deal with that case by mapping it to a MapToError::FrameAllocationFailed error through the Option::ok_or method and then applying the question mark operator to return early in the case of an error.
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    // set the required PRESENT flag and the WRITABLE flag for the page. 
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe {
+        // use the Mapper::map_to method for creating the mapping in the active page table.
+        // The method can fail, so we use "?" again to forward the error to the caller.
+        // On success, the method returns a MapperFlush instance that we can use to update the translation lookaside buffer using the flush method.
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush()
+    };
+    Ok(())
+}
+
+/// Maps additional pages starting at `heap_top` so that the heap can grow by at least `min_size` bytes.
+///
+/// Returns the number of bytes that were mapped. This can be less than `min_size` (or zero) if the heap limit is reached,
+/// the frames run out, or the global mapper from `memory::init_global` is not available.
+fn grow_heap(heap_top: usize, min_size: usize) -> usize {
+    let heap_end_limit = HEAP_START + HEAP_LIMIT.load(Ordering::Relaxed);
+    let size = align_up(min_size.max(HEAP_GROW_SIZE), PAGE_SIZE)
+        .min(heap_end_limit.saturating_sub(heap_top));
+    if size < min_size {
+        return 0;
+    }
+
+    let mapped = memory::with_global(|mapper, frame_allocator| {
+        let mut mapped = 0;
+        while mapped < size {
+            let page = Page::containing_address(VirtAddr::new((heap_top + mapped) as u64));
+            if map_heap_page(page, mapper, frame_allocator).is_err() {
+                break;
+            }
+            mapped += PAGE_SIZE;
+        }
+        mapped
+    })
+    .unwrap_or(0);
+    stats::record_heap_growth(mapped);
+    mapped
+}
+
 /// We can't use `unsafe impl GlobalAlloc for spin::Mutex<BumpAllocator> {...}` 
 /// because the Rust compiler does not permit trait implementations for types defined in other crates
 /// we need to create our own wrapper type around spin::Mutex
+///
+/// Interrupts are disabled while the lock is held, like in `vga_buffer::_print`. Otherwise an interrupt handler (or, in the future,
+/// a preempting task) that allocates while the interrupted code holds the lock would spin forever.
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
 }
@@ -89,8 +267,60 @@ impl <A> Locked<A> {
         Locked { inner: spin::Mutex::new(inner), }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
-        self.inner.lock()
+    /// Disables interrupts and locks the wrapped value.
+    ///
+    /// The previous interrupt state is restored when the returned guard is dropped.
+    pub fn lock(&self) -> LockedGuard<'_, A> {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let guard = match self.inner.try_lock() {
+            Some(guard) => guard,
+            None => {
+                // The kernel runs on a single CPU and interrupts are disabled, so nobody else can release the lock while we wait.
+                // The lock must be held by code further up the call stack, i.e. by the code that was interrupted or that re-entered the allocator.
+                debug_assert!(
+                    false,
+                    "Locked<{}> locked re-entrantly on the same CPU, this would deadlock",
+                    core::any::type_name::<A>()
+                );
+                self.inner.lock()
+            }
+        };
+        LockedGuard {
+            guard: ManuallyDrop::new(guard),
+            interrupts_enabled,
+        }
+    }
+}
+
+/// The guard returned by `Locked::lock`.
+pub struct LockedGuard<'a, A> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, A>>,
+    // whether interrupts were enabled before the lock was taken
+    interrupts_enabled: bool,
+}
+
+impl<A> Deref for LockedGuard<'_, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.guard
+    }
+}
+
+impl<A> DerefMut for LockedGuard<'_, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.guard
+    }
+}
+
+impl<A> Drop for LockedGuard<'_, A> {
+    fn drop(&mut self) {
+        // release the lock before enabling interrupts again, so that an interrupt cannot see the lock still taken
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
     }
 }
 
@@ -100,4 +330,3 @@ fn align_up(addr: usize, align: usize) -> usize {
     //  to create a bitmask to align the address in a very efficient way.
     (addr + align - 1) & !(align - 1)
 }
-
@@ -0,0 +1,172 @@
+// Arena allocation for short-lived tasks.
+//
+// An `Arena` hands out memory from chunks that it allocates from the kernel heap, and gives the chunks back only when
+// it is dropped or reset. Freeing a single allocation does nothing (except for the most recent one), so a task that
+// builds many temporary structures does not leave the size classes of the global allocator fragmented. Collections
+// use an arena through the `allocator_api`:
+//
+//     let arena = Arena::new();
+//     let mut scratch: Vec<u64, &Arena> = Vec::new_in(&arena);
+//
+// Created inside an async task, the arena lives in the task's future and is dropped when the task completes.
+// An arena is not `Sync`, it belongs to a single task.
+
+use super::align_up;
+use alloc::alloc::{alloc, dealloc, AllocError, Allocator, Layout};
+use core::cell::Cell;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+/// Size and alignment of the chunks that an arena requests from the kernel heap.
+///
+/// Allocations that do not fit into a chunk of this size get a larger chunk (a multiple of `CHUNK_SIZE`).
+pub const CHUNK_SIZE: usize = 4096;
+
+/// The header at the start of every chunk, linking all chunks of an arena.
+struct ChunkHeader {
+    next: Option<NonNull<ChunkHeader>>,
+    layout: Layout,
+}
+
+/// A region allocator that frees all of its allocations at once.
+pub struct Arena {
+    // the most recently allocated chunk, which is the one that allocations are taken from
+    chunks: Cell<Option<NonNull<ChunkHeader>>>,
+    // the unused part of the current chunk
+    next: Cell<usize>,
+    end: Cell<usize>,
+}
+
+impl Arena {
+    /// Creates an empty arena. The first chunk is allocated on the first allocation.
+    pub const fn new() -> Self {
+        Arena {
+            chunks: Cell::new(None),
+            next: Cell::new(0),
+            end: Cell::new(0),
+        }
+    }
+
+    /// Returns the total size of the chunks that the arena holds.
+    pub fn capacity(&self) -> usize {
+        let mut capacity = 0;
+        let mut chunk = self.chunks.get();
+        while let Some(header) = chunk {
+            let header = unsafe { header.as_ref() };
+            capacity += header.layout.size();
+            chunk = header.next;
+        }
+        capacity
+    }
+
+    /// Gives all chunks back to the kernel heap.
+    ///
+    /// This takes `&mut self`, so no allocation from the arena can be alive anymore.
+    pub fn reset(&mut self) {
+        let mut chunk = self.chunks.take();
+        while let Some(header) = chunk {
+            unsafe {
+                let ChunkHeader { next, layout } = header.as_ptr().read();
+                dealloc(header.as_ptr() as *mut u8, layout);
+                chunk = next;
+            }
+        }
+        self.next.set(0);
+        self.end.set(0);
+    }
+
+    /// Allocates from the unused part of the current chunk.
+    fn alloc_from_chunk(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        self.chunks.get()?;
+        let start = align_up(self.next.get(), layout.align());
+        let end = start.checked_add(layout.size())?;
+        if end > self.end.get() {
+            return None;
+        }
+        self.next.set(end);
+        let ptr = NonNull::new(start as *mut u8)?;
+        Some(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    /// Allocates a new chunk that is large enough for `layout` and makes it the current chunk.
+    ///
+    /// The rest of the previous chunk stays unused until the arena is reset.
+    fn add_chunk(&self, layout: Layout) -> Result<(), AllocError> {
+        let header_size = mem::size_of::<ChunkHeader>();
+        // in the worst case, the allocation needs `align - 1` bytes of padding behind the header
+        let size = header_size
+            .checked_add(layout.align() - 1)
+            .and_then(|size| size.checked_add(layout.size()))
+            .ok_or(AllocError)?;
+        let chunk_layout = Layout::from_size_align(align_up(size, CHUNK_SIZE), CHUNK_SIZE)
+            .map_err(|_| AllocError)?;
+        let header = NonNull::new(unsafe { alloc(chunk_layout) } as *mut ChunkHeader).ok_or(AllocError)?;
+        unsafe {
+            header.as_ptr().write(ChunkHeader {
+                next: self.chunks.get(),
+                layout: chunk_layout,
+            });
+        }
+        let start = header.as_ptr() as usize;
+        self.chunks.set(Some(header));
+        self.next.set(start + header_size);
+        self.end.set(start + chunk_layout.size());
+        Ok(())
+    }
+
+    /// Returns whether the allocation at `ptr` is the most recent one, i.e. whether it ends at the start of the unused memory.
+    fn is_last(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        ptr.as_ptr() as usize + layout.size() == self.next.get()
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if let Some(ptr) = self.alloc_from_chunk(layout) {
+            return Ok(ptr);
+        }
+        self.add_chunk(layout)?;
+        self.alloc_from_chunk(layout).ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // only the most recent allocation can be given back, everything else is freed together with the arena
+        if self.is_last(ptr, layout) {
+            self.next.set(ptr.as_ptr() as usize);
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // the most recent allocation can grow in place if the current chunk has enough room, e.g. a Vec that is filled in a loop
+        let start = ptr.as_ptr() as usize;
+        if self.is_last(ptr, old_layout) && start.is_multiple_of(new_layout.align()) {
+            if let Some(end) = start.checked_add(new_layout.size()) {
+                if end <= self.end.get() {
+                    self.next.set(end);
+                    return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+                }
+            }
+        }
+
+        let new_ptr = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.cast::<u8>().as_ptr(), old_layout.size());
+        Ok(new_ptr)
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
@@ -0,0 +1,201 @@
+// Heap corruption detection for the `alloc-debug` feature.
+//
+// Every allocation is surrounded by red zones filled with `GUARD_BYTE`. Freed allocations are
+// filled with `POISON_BYTE` and kept in a quarantine for a while before they are really freed,
+// so that writes through dangling pointers can still be detected. Both are validated when an
+// allocation is freed, when it leaves the quarantine, and whenever `check_heap` is called.
+
+use super::{align_up, Locked};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Size of the red zone behind every allocation (the one in front can be larger because of alignment).
+const RED_ZONE: usize = 16;
+/// Pattern of the red zones.
+const GUARD_BYTE: u8 = 0xfd;
+/// Pattern of freed memory in the quarantine.
+const POISON_BYTE: u8 = 0xdd;
+/// Number of live allocations whose red zones are checked by `check_heap`.
+const MAX_TRACKED: usize = 1024;
+/// Number of freed allocations that are held back before they are really freed.
+const QUARANTINE_SIZE: usize = 64;
+
+/// An allocation as seen by the user, i.e. without red zones.
+#[derive(Debug, Clone, Copy)]
+struct Allocation {
+    addr: usize,
+    layout: Layout,
+}
+
+impl Allocation {
+    /// Returns the layout including red zones and the offset of the user data in it.
+    fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+        // the front red zone must keep the user data aligned
+        let front = align_up(RED_ZONE, layout.align());
+        let size = front.checked_add(layout.size())?.checked_add(RED_ZONE)?;
+        let outer = Layout::from_size_align(size, layout.align()).ok()?;
+        Some((outer, front))
+    }
+
+    fn front(&self) -> usize {
+        align_up(RED_ZONE, self.layout.align())
+    }
+
+    fn outer_start(&self) -> usize {
+        self.addr - self.front()
+    }
+
+    /// Panics if one of the red zones of this allocation was overwritten.
+    unsafe fn check_red_zones(&self) {
+        if let Some(offset) = find_mismatch(self.outer_start(), self.front(), GUARD_BYTE) {
+            panic!(
+                "heap corruption: red zone {} bytes before allocation at {:#x} ({:?}) was overwritten",
+                self.front() - offset,
+                self.addr,
+                self.layout
+            );
+        }
+        let back = self.addr + self.layout.size();
+        if let Some(offset) = find_mismatch(back, RED_ZONE, GUARD_BYTE) {
+            panic!(
+                "heap corruption: red zone {} bytes after allocation at {:#x} ({:?}) was overwritten",
+                offset,
+                self.addr,
+                self.layout
+            );
+        }
+    }
+
+    /// Panics if the poisoned memory of this freed allocation was written to.
+    unsafe fn check_poison(&self) {
+        let (outer, _) = Self::outer_layout(self.layout).unwrap();
+        if let Some(offset) = find_mismatch(self.outer_start(), outer.size(), POISON_BYTE) {
+            panic!(
+                "heap corruption: write to freed allocation at {:#x} ({:?}) at offset {}",
+                self.addr,
+                self.layout,
+                offset as isize - self.front() as isize
+            );
+        }
+    }
+}
+
+/// Returns the offset of the first byte in the given range that is not `pattern`.
+unsafe fn find_mismatch(start: usize, len: usize, pattern: u8) -> Option<usize> {
+    (0..len).find(|&offset| ptr::read_volatile((start + offset) as *const u8) != pattern)
+}
+
+struct DebugState {
+    live: [Option<Allocation>; MAX_TRACKED],
+    // number of live allocations that did not fit into `live`
+    untracked: usize,
+    // ring buffer of freed allocations
+    quarantine: [Option<Allocation>; QUARANTINE_SIZE],
+    next_quarantine: usize,
+}
+
+impl DebugState {
+    const fn new() -> Self {
+        const NONE: Option<Allocation> = None;
+        DebugState {
+            live: [NONE; MAX_TRACKED],
+            untracked: 0,
+            quarantine: [NONE; QUARANTINE_SIZE],
+            next_quarantine: 0,
+        }
+    }
+}
+
+// Lock order: STATE is always locked before the inner allocator, never the other way around.
+static STATE: Locked<DebugState> = Locked::new(DebugState::new());
+
+/// Allocates `layout` from `inner` with red zones around it.
+pub(super) unsafe fn alloc(inner: &impl GlobalAlloc, layout: Layout) -> *mut u8 {
+    let (outer, front) = match Allocation::outer_layout(layout) {
+        Some(outer) => outer,
+        None => return ptr::null_mut(),
+    };
+    let outer_ptr = inner.alloc(outer);
+    if outer_ptr.is_null() {
+        return outer_ptr;
+    }
+    ptr::write_bytes(outer_ptr, GUARD_BYTE, front);
+    ptr::write_bytes(outer_ptr.add(front + layout.size()), GUARD_BYTE, RED_ZONE);
+
+    let allocation = Allocation {
+        addr: outer_ptr as usize + front,
+        layout,
+    };
+    let mut state = STATE.lock();
+    match state.live.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(allocation),
+        None => state.untracked += 1,
+    }
+    allocation.addr as *mut u8
+}
+
+/// Validates the red zones of the allocation at `ptr`, poisons it and moves it into the quarantine.
+///
+/// The allocation that has been in the quarantine the longest is given back to `inner`.
+pub(super) unsafe fn dealloc(inner: &impl GlobalAlloc, ptr: *mut u8, layout: Layout) {
+    let allocation = Allocation {
+        addr: ptr as usize,
+        layout,
+    };
+    let mut state = STATE.lock();
+    // check for a double free first, since the red zones of a freed allocation are poisoned
+    if state.quarantine.iter().flatten().any(|a| a.addr == allocation.addr) {
+        panic!("double free of allocation at {:#x} ({:?})", allocation.addr, layout);
+    }
+    allocation.check_red_zones();
+    let DebugState { live, untracked, .. } = &mut *state;
+    match live.iter_mut().find(|slot| matches!(slot, Some(a) if a.addr == allocation.addr)) {
+        Some(slot) => *slot = None,
+        None if *untracked > 0 => *untracked -= 1,
+        None => panic!("free of unknown allocation at {:#x} ({:?})", allocation.addr, layout),
+    }
+
+    let (outer, _) = Allocation::outer_layout(layout).unwrap();
+    ptr::write_bytes(allocation.outer_start() as *mut u8, POISON_BYTE, outer.size());
+
+    let index = state.next_quarantine;
+    state.next_quarantine = (index + 1) % QUARANTINE_SIZE;
+    if let Some(evicted) = state.quarantine[index].replace(allocation) {
+        evicted.check_poison();
+        let (outer, _) = Allocation::outer_layout(evicted.layout).unwrap();
+        inner.dealloc(evicted.outer_start() as *mut u8, outer);
+    }
+}
+
+/// Gives all quarantined allocations back to `inner` and returns the number of bytes released.
+///
+/// Called when the heap is exhausted. The poison of every allocation is still validated before it is freed.
+pub(super) unsafe fn flush_quarantine(inner: &impl GlobalAlloc) -> usize {
+    let mut state = STATE.lock();
+    let mut released = 0;
+    for slot in state.quarantine.iter_mut() {
+        if let Some(allocation) = slot.take() {
+            allocation.check_poison();
+            let (outer, _) = Allocation::outer_layout(allocation.layout).unwrap();
+            inner.dealloc(allocation.outer_start() as *mut u8, outer);
+            released += outer.size();
+        }
+    }
+    released
+}
+
+/// Validates the red zones of all tracked live allocations and the poison of all quarantined ones.
+///
+/// Panics with the address and layout of the first corrupted allocation. The executor calls this
+/// regularly when the `alloc-debug` feature is enabled, but it can be called at any time.
+pub fn check_heap() {
+    let state = STATE.lock();
+    unsafe {
+        for allocation in state.live.iter().flatten() {
+            allocation.check_red_zones();
+        }
+        for allocation in state.quarantine.iter().flatten() {
+            allocation.check_poison();
+        }
+    }
+}
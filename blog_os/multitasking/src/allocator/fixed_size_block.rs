@@ -1,6 +1,6 @@
 use alloc::alloc::Layout;
 use core::ptr;
-use super::Locked;
+use super::{grow_heap, Locked};
 use alloc::alloc::GlobalAlloc;
 use core::{mem, ptr::NonNull};
 
@@ -15,7 +15,7 @@ struct ListNode {
 /// the block alignment (alignments must be always powers of 2).
 /// 
 /// We don’t define any block sizes smaller than 8 because each block must be capable of storing a 64-bit pointer to the next block when freed. 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 /// Calculating the list index
 /// Choose an appropriate block size for the given layout.
@@ -26,11 +26,23 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+/// Usage of a single size class of the `FixedSizeBlockAllocator`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeClassStats {
+    /// Number of blocks of this size that are currently allocated.
+    pub in_use: usize,
+    /// Number of blocks of this size that are in the free list.
+    pub free: usize,
+}
+
 pub struct FixedSizeBlockAllocator {
     // The list_heads field is an array of head pointers, one for each block size. This is implemented by using the len() of the BLOCK_SIZES slice as the array length. 
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     // As a fallback allocator for allocations larger than the largest block size, we use the allocator provided by the linked_list_allocator.
     fallback_allocator: linked_list_allocator::Heap,
+    // usage counters for `allocator::stats`, one entry per block size
+    size_classes: [SizeClassStats; BLOCK_SIZES.len()],
+    fallback_allocations: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -43,6 +55,8 @@ impl FixedSizeBlockAllocator {
             // Initializing the array directly as [None; BLOCK_SIZES.len()] does not work, because then the compiler requires Option<&'static mut ListNode> to implement the Copy trait, which it does not. 
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            size_classes: [SizeClassStats { in_use: 0, free: 0 }; BLOCK_SIZES.len()],
+            fallback_allocations: 0,
         }
     }
 
@@ -57,10 +71,57 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size)
     }
 
+    /// Returns the usage of every size class, in the order of `BLOCK_SIZES`.
+    pub fn size_classes(&self) -> [SizeClassStats; BLOCK_SIZES.len()] {
+        self.size_classes
+    }
+
+    /// Returns the number of allocations that were passed to the fallback allocator.
+    pub fn fallback_allocations(&self) -> usize {
+        self.fallback_allocations
+    }
+
+    /// Gives every block in the free lists back to the fallback allocator and returns the number of bytes released.
+    ///
+    /// The fallback allocator merges the blocks with their free neighbours, so larger allocations can use the memory again.
+    /// The lists are refilled lazily afterwards, like after `init`.
+    pub fn release_free_blocks(&mut self) -> usize {
+        let mut released = 0;
+        for (index, &block_size) in BLOCK_SIZES.iter().enumerate() {
+            // the blocks were allocated from the fallback allocator with this layout
+            let layout = Layout::from_size_align(block_size, block_size).unwrap();
+            while let Some(node) = self.list_heads[index].take() {
+                self.list_heads[index] = node.next.take();
+                unsafe {
+                    self.fallback_allocator.deallocate(NonNull::from(node).cast(), layout);
+                }
+                released += block_size;
+            }
+            self.size_classes[index].free = 0;
+        }
+        released
+    }
+
     /// Allocates using the fallback allocator.
+    ///
+    /// If the fallback allocator is exhausted, the heap is grown by mapping more pages and the allocation is retried once.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
+        self.fallback_allocations += 1;
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
             // [allocate_first_fit] returns a Result<NonNull<u8>, ()>
+            return ptr.as_ptr();
+        }
+
+        // the new memory is appended at the heap top, so in the worst case we need room for the alignment padding too
+        let grown = grow_heap(self.fallback_allocator.top(), layout.size() + layout.align());
+        if grown == 0 {
+            return ptr::null_mut();
+        }
+        unsafe {
+            // the grown range was just mapped and directly follows the current heap
+            self.fallback_allocator.extend(grown);
+        }
+        match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
         }
@@ -82,6 +143,8 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                     // 4.1 If the list is not empty, we enter the Some(node) branch of the match statement, where we point the head pointer of the list to the successor of the popped node (by using take again)
                     Some(node) => {
                         allocator.list_heads[index] = node.next.take();
+                        allocator.size_classes[index].free -= 1;
+                        allocator.size_classes[index].in_use += 1;
                         // 5. return the popped node pointer as a *mut u8
                         node as *mut ListNode as *mut u8
                     }
@@ -96,7 +159,11 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                         // 6. create a new Layout from it and call the fallback_alloc method to perform the allocation.
                         let layout = Layout::from_size_align(block_size, block_align)
                             .unwrap();
-                        allocator.fallback_alloc(layout)
+                        let ptr = allocator.fallback_alloc(layout);
+                        if !ptr.is_null() {
+                            allocator.size_classes[index].in_use += 1;
+                        }
+                        ptr
                     }
                 }
             }
@@ -123,6 +190,8 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 new_node_ptr.write(new_node);
                 // set the head pointer of the list, which is currently None since we called take on it, to our newly written ListNode. 
                 allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                allocator.size_classes[index].in_use -= 1;
+                allocator.size_classes[index].free += 1;
             }
             // If the index is None, no fitting block size exists in BLOCK_SIZES, 
             // which indicates that the allocation was created by the fallback allocator. 
@@ -0,0 +1,146 @@
+use super::Locked;
+use crate::{backtrace, serial_println, symbols};
+use alloc::alloc::Layout;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Maximum number of live allocations that can be tracked at the same time.
+const MAX_TRACKED: usize = 256;
+
+/// A live allocation recorded by the leak tracker.
+#[derive(Debug, Clone, Copy)]
+struct TrackedAllocation {
+    addr: usize,
+    layout: Layout,
+    // return address into the code that called the allocator, 0 if unknown
+    caller: usize,
+}
+
+struct LeakTable {
+    entries: [Option<TrackedAllocation>; MAX_TRACKED],
+    // number of allocations that did not fit into the table
+    overflowed: usize,
+}
+
+// The tracker is opt-in: as long as TRACKING is false, the global allocator only pays for a single atomic load.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static TABLE: Locked<LeakTable> = Locked::new(LeakTable {
+    entries: [None; MAX_TRACKED],
+    overflowed: 0,
+});
+
+/// Records a new allocation if leak checking is active.
+pub(super) fn record_alloc(ptr: *mut u8, layout: Layout) {
+    if ptr.is_null() || !TRACKING.load(Ordering::Relaxed) {
+        return;
+    }
+    let caller = allocating_caller();
+    let mut table = TABLE.lock();
+    let allocation = TrackedAllocation {
+        addr: ptr as usize,
+        layout,
+        caller,
+    };
+    match table.entries.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(allocation),
+        None => table.overflowed += 1,
+    }
+}
+
+/// Returns the return address into the code that called the allocator, or 0 if it cannot be found.
+///
+/// The frames of the allocator and of the `alloc` crate (`Box::new`, `Vec::push`, ...) are skipped. They are only
+/// recognized by their names, so without the symbol table (see `symbols`), the caller is unknown.
+fn allocating_caller() -> usize {
+    if !symbols::is_available() {
+        return 0;
+    }
+    let mut caller = 0;
+    backtrace::trace(|address| {
+        if caller != 0 {
+            return;
+        }
+        match symbols::lookup(address) {
+            Some(symbol) if is_allocator_frame(symbol.name) => {}
+            _ => caller = address.as_u64() as usize,
+        }
+    });
+    caller
+}
+
+fn is_allocator_frame(name: &str) -> bool {
+    // `__rust_alloc` and friends are the shims that forward to the global allocator
+    ["__rust_", "__rg_", "__rdl_", "alloc::", "<alloc::", "core::alloc::"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || name.contains("blog_os::allocator::")
+}
+
+/// Removes a freed allocation from the table.
+///
+/// Allocations that were made before `leak_check_begin` are not in the table and are ignored.
+pub(super) fn record_dealloc(ptr: *mut u8) {
+    if !TRACKING.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut table = TABLE.lock();
+    let addr = ptr as usize;
+    if let Some(slot) = table
+        .entries
+        .iter_mut()
+        .find(|slot| matches!(slot, Some(a) if a.addr == addr))
+    {
+        *slot = None;
+    }
+}
+
+/// Starts recording all allocations of the global allocator.
+///
+/// Every allocation made between this call and `leak_check_end` must be freed before
+/// `leak_check_end` is called.
+pub fn leak_check_begin() {
+    let mut table = TABLE.lock();
+    table.entries = [None; MAX_TRACKED];
+    table.overflowed = 0;
+    TRACKING.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording allocations and panics if any allocation since `leak_check_begin` is still live.
+///
+/// Every leaked allocation is logged to serial with its address, layout and the address of the code that allocated it.
+pub fn leak_check_end() {
+    TRACKING.store(false, Ordering::Relaxed);
+    let table = TABLE.lock();
+    let mut leaked = 0;
+    for allocation in table.entries.iter().flatten() {
+        match symbols::lookup(x86_64::VirtAddr::new(allocation.caller as u64)) {
+            Some(symbol) => {
+                serial_println!(
+                    "leaked allocation at {:#x}: {:?}, allocated from {:#x} ({})",
+                    allocation.addr,
+                    allocation.layout,
+                    allocation.caller,
+                    symbol
+                );
+            }
+            None => {
+                serial_println!(
+                    "leaked allocation at {:#x}: {:?}, allocated from {:#x}",
+                    allocation.addr,
+                    allocation.layout,
+                    allocation.caller
+                );
+            }
+        }
+        leaked += 1;
+    }
+    if table.overflowed > 0 {
+        serial_println!(
+            "WARNING: {} allocations were not tracked because the leak table was full",
+            table.overflowed
+        );
+    }
+    drop(table);
+    if leaked > 0 {
+        panic!("{} allocations leaked", leaked);
+    }
+}
@@ -45,23 +45,46 @@ impl LinkedListAllocator {
         self.add_free_region(heap_start, heap_size);
     }
 
-    /// Adds the given memory region to the front of the list.
-    /// provides the fundamental push operation on the linked list.
+    /// Adds the given memory region to the list.
+    ///
+    /// The list is kept sorted by address, so a freed region can be merged with the regions directly before and after it.
+    /// Without merging, the heap fragments into many small regions over time, even if all of them are adjacent.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
-        // 1. creates a new node on its stack with the size of the freed region
+        // 1. find the last node that starts before the freed region
+        //    The dummy head node counts as starting before every region.
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|next| next.start_addr() < addr) {
+            current = current.next.as_mut().unwrap();
+        }
+
+        // 2. create a new node for the freed region that points to the successor of `current`
+        //    If the successor starts directly at the end of the freed region, it is absorbed into the new node.
         let mut node = ListNode::new(size);
-        // 2. uses the Option::take method to set the next pointer of the node to the current head pointer, 
-        //    thereby resetting the head pointer to None.
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
-        // 3. writes the newly created node to the beginning of the freed memory region through the write method.
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr)
+        node.next = current.next.take();
+        if let Some(next) = node.next.take() {
+            if addr + size == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        // 3. if `current` ends directly at the start of the freed region, grow it instead of inserting the new node
+        //    The dummy head node has size 0 and is never merged.
+        if current.size > 0 && current.end_addr() == addr {
+            current.size += node.size;
+            current.next = node.next.take();
+        } else {
+            // writes the newly created node to the beginning of the freed memory region through the write method.
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(node);
+            current.next = Some(&mut *node_ptr);
+        }
     }
 
     /// Looks for a free region with the given size and alignment and removes
@@ -99,7 +122,12 @@ impl LinkedListAllocator {
         -> Result<usize, ()>
     {
         // calculates the start and end address of a potential allocation
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        let front_padding = alloc_start - region.start_addr();
+        if front_padding > 0 && front_padding < mem::size_of::<ListNode>() {
+            // the padding in front of the allocation is given back to the free list, so it must be able to hold a ListNode too
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -154,6 +182,11 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
             // calculates the end address of the allocation and the excess size again.
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
             let excess_size = region.end_addr() - alloc_end;
+            let (region_start, front_padding) = (region.start_addr(), alloc_start - region.start_addr());
+            // give the alignment padding in front of the allocation back to the free list instead of leaking it
+            if front_padding > 0 {
+                allocator.add_free_region(region_start, front_padding);
+            }
             // If the excess size is not null, it calls add_free_region to add the excess size of the memory region back to the free list
             if excess_size > 0 {
                 allocator.add_free_region(alloc_end, excess_size);
@@ -0,0 +1,46 @@
+// Out-of-memory recovery.
+//
+// When an allocation fails, the `KernelAllocator` first empties the caches of the allocators themselves and then calls
+// the handlers registered here, so that other subsystems can drop memory they can do without. If anything was freed,
+// the allocation is retried once before it is reported as failed.
+
+use super::Locked;
+use alloc::alloc::Layout;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A function that tries to free memory after an allocation with the given layout failed.
+///
+/// Returns the number of bytes it freed. Handlers are called without any allocator lock held, so they can free memory
+/// normally, but they must not block.
+pub type OomHandler = fn(Layout) -> usize;
+
+/// Maximum number of handlers that can be registered.
+const MAX_HANDLERS: usize = 8;
+
+/// Error returned by `register_oom_handler` when all handler slots are taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyHandlers;
+
+static HANDLERS: Locked<[Option<OomHandler>; MAX_HANDLERS]> = Locked::new([None; MAX_HANDLERS]);
+// set while the handlers run, so that an allocation that fails inside a handler does not call them again
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Registers a handler that is called when the heap is exhausted.
+pub fn register_oom_handler(handler: OomHandler) -> Result<(), TooManyHandlers> {
+    let mut handlers = HANDLERS.lock();
+    let slot = handlers.iter_mut().find(|slot| slot.is_none()).ok_or(TooManyHandlers)?;
+    *slot = Some(handler);
+    Ok(())
+}
+
+/// Calls all registered handlers and returns the total number of bytes they freed.
+pub(super) fn run_handlers(layout: Layout) -> usize {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    // copy the handlers, so that the lock is not held while they run
+    let handlers = *HANDLERS.lock();
+    let freed = handlers.iter().flatten().map(|handler| handler(layout)).sum();
+    RUNNING.store(false, Ordering::Release);
+    freed
+}
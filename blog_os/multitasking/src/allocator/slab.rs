@@ -0,0 +1,363 @@
+use super::{align_up, Locked};
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::{
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+/// The size (and alignment) of a single slab.
+///
+/// Slabs are requested from the global allocator, where allocations of this size bypass the
+/// fixed-size block lists. This way, an empty slab really goes back to the general-purpose heap.
+const SLAB_SIZE: usize = 4096;
+
+/// A free object slot. Like the `ListNode` of the fixed-size block allocator, the node is
+/// stored in the free slot itself.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// The header at the start of every slab.
+struct Slab {
+    // neighbours in the list of partial or full slabs
+    // The list is doubly linked, so that a slab can move between the lists without walking them.
+    next: Option<NonNull<Slab>>,
+    prev: Option<NonNull<Slab>>,
+    // free slots of this slab
+    free: Option<NonNull<FreeObject>>,
+    // number of slots that are currently allocated
+    in_use: usize,
+}
+
+/// Occupancy information of a `SlabCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabCacheStats {
+    /// Number of slabs currently owned by the cache.
+    pub slabs: usize,
+    /// Number of slabs without a free slot.
+    pub full_slabs: usize,
+    /// Number of slabs with both allocated and free slots.
+    pub partial_slabs: usize,
+    /// Number of slabs without allocated objects, which is at most one.
+    pub empty_slabs: usize,
+    /// Number of objects that can be stored in a single slab.
+    pub objects_per_slab: usize,
+    /// Number of objects that are currently allocated.
+    pub objects_in_use: usize,
+}
+
+/// The type-erased part of a slab cache.
+struct RawSlabCache {
+    object_size: usize,
+    object_align: usize,
+    // slabs that have both allocated and free slots, which are the ones that allocations are taken from
+    partial: Option<NonNull<Slab>>,
+    // slabs without a free slot
+    full: Option<NonNull<Slab>>,
+    // a single slab without allocated objects, kept so that freeing and allocating one object does not release and
+    // allocate a slab every time
+    empty: Option<NonNull<Slab>>,
+    slab_count: usize,
+    full_count: usize,
+    partial_count: usize,
+    objects_in_use: usize,
+}
+
+// The raw pointers only point into slabs that are owned by the cache.
+unsafe impl Send for RawSlabCache {}
+
+impl RawSlabCache {
+    const fn new(size: usize, align: usize) -> Self {
+        // every slot must be able to hold a FreeObject when it is not in use
+        let object_align = if align > mem::align_of::<FreeObject>() {
+            align
+        } else {
+            mem::align_of::<FreeObject>()
+        };
+        let object_size = if size > mem::size_of::<FreeObject>() {
+            size
+        } else {
+            mem::size_of::<FreeObject>()
+        };
+        RawSlabCache {
+            // round up so that every slot in the slab stays aligned
+            object_size: (object_size + object_align - 1) & !(object_align - 1),
+            object_align,
+            partial: None,
+            full: None,
+            empty: None,
+            slab_count: 0,
+            full_count: 0,
+            partial_count: 0,
+            objects_in_use: 0,
+        }
+    }
+
+    /// Offset of the first slot from the start of a slab.
+    fn objects_offset(&self) -> usize {
+        align_up(mem::size_of::<Slab>(), self.object_align)
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        SLAB_SIZE.saturating_sub(self.objects_offset()) / self.object_size
+    }
+
+    fn stats(&self) -> SlabCacheStats {
+        SlabCacheStats {
+            slabs: self.slab_count,
+            full_slabs: self.full_count,
+            partial_slabs: self.partial_count,
+            empty_slabs: self.empty.iter().count(),
+            objects_per_slab: self.objects_per_slab(),
+            objects_in_use: self.objects_in_use,
+        }
+    }
+
+    /// Calls `f` with the number of allocated objects of every slab that has any.
+    fn for_each_slab(&self, mut f: impl FnMut(usize)) {
+        for list in [self.full, self.partial] {
+            let mut current = list;
+            while let Some(slab_ptr) = current {
+                let slab = unsafe { slab_ptr.as_ref() };
+                f(slab.in_use);
+                current = slab.next;
+            }
+        }
+    }
+
+    /// Takes a free slot from a partial slab, falling back to the empty slab and then to a new slab.
+    fn alloc(&mut self) -> Option<NonNull<u8>> {
+        assert!(
+            self.objects_per_slab() > 0,
+            "object too large for a slab cache"
+        );
+        // 1. every partial slab has a free slot, so only the first one is looked at
+        let mut slab_ptr = match self.partial {
+            Some(slab_ptr) => slab_ptr,
+            None => {
+                // 2. no partial slab -> use the empty slab or create a new one
+                let slab_ptr = match self.empty.take() {
+                    Some(slab_ptr) => slab_ptr,
+                    None => self.new_slab()?,
+                };
+                unsafe { Self::push(&mut self.partial, slab_ptr) };
+                self.partial_count += 1;
+                slab_ptr
+            }
+        };
+
+        let slab = unsafe { slab_ptr.as_mut() };
+        let mut object = slab.free.expect("partial slab has no free slot");
+        slab.free = unsafe { object.as_mut().next.take() };
+        slab.in_use += 1;
+        self.objects_in_use += 1;
+        // 3. a slab whose last slot was taken moves to the full list
+        if slab.free.is_none() {
+            unsafe {
+                Self::remove(&mut self.partial, slab_ptr);
+                Self::push(&mut self.full, slab_ptr);
+            }
+            self.partial_count -= 1;
+            self.full_count += 1;
+        }
+        Some(object.cast())
+    }
+
+    /// Returns the slot at `ptr` to its slab and releases the slab if it became empty.
+    ///
+    /// One empty slab is kept, so that a cache whose last object is freed and allocated again over and over does not
+    /// allocate and release a slab every time.
+    ///
+    /// This function is unsafe because `ptr` must have been returned by `alloc` of this cache.
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>) {
+        // slabs are aligned to their size, so the slab header is found by rounding down
+        let slab_addr = ptr.as_ptr() as usize & !(SLAB_SIZE - 1);
+        let mut slab_ptr = NonNull::new_unchecked(slab_addr as *mut Slab);
+        let slab = slab_ptr.as_mut();
+
+        let was_full = slab.free.is_none();
+        let object_ptr = ptr.cast::<FreeObject>();
+        object_ptr.as_ptr().write(FreeObject { next: slab.free });
+        slab.free = Some(object_ptr);
+        slab.in_use -= 1;
+        self.objects_in_use -= 1;
+
+        // take the slab out of the list that it was in ...
+        if was_full {
+            Self::remove(&mut self.full, slab_ptr);
+            self.full_count -= 1;
+        } else if slab.in_use == 0 {
+            Self::remove(&mut self.partial, slab_ptr);
+            self.partial_count -= 1;
+        } else {
+            return;
+        }
+        // ... and put it into the one it belongs to now
+        if slab.in_use > 0 {
+            Self::push(&mut self.partial, slab_ptr);
+            self.partial_count += 1;
+        } else if self.empty.is_none() {
+            self.empty = Some(slab_ptr);
+        } else {
+            self.release_slab(slab_ptr);
+        }
+    }
+
+    /// Allocates a new slab and links all of its slots into its free list.
+    ///
+    /// The slab is not part of any list yet.
+    fn new_slab(&mut self) -> Option<NonNull<Slab>> {
+        let layout = Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap();
+        let slab_ptr = NonNull::new(unsafe { alloc(layout) })?.cast::<Slab>();
+
+        // push the slots in reverse order so that the lowest address is handed out first
+        let mut free = None;
+        for index in (0..self.objects_per_slab()).rev() {
+            let addr = slab_ptr.as_ptr() as usize + self.objects_offset() + index * self.object_size;
+            let object_ptr = addr as *mut FreeObject;
+            unsafe { object_ptr.write(FreeObject { next: free }) };
+            free = NonNull::new(object_ptr);
+        }
+
+        unsafe {
+            slab_ptr.as_ptr().write(Slab {
+                next: None,
+                prev: None,
+                free,
+                in_use: 0,
+            });
+        }
+        self.slab_count += 1;
+        Some(slab_ptr)
+    }
+
+    /// Gives the memory of the given empty slab, which is not part of any list, back to the heap.
+    fn release_slab(&mut self, slab_ptr: NonNull<Slab>) {
+        self.slab_count -= 1;
+        let layout = Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap();
+        unsafe { dealloc(slab_ptr.as_ptr() as *mut u8, layout) };
+    }
+
+    /// Adds the slab to the front of the given list.
+    ///
+    /// This function is unsafe because the slab must not be part of a list.
+    unsafe fn push(list: &mut Option<NonNull<Slab>>, mut slab_ptr: NonNull<Slab>) {
+        let slab = slab_ptr.as_mut();
+        slab.prev = None;
+        slab.next = *list;
+        if let Some(mut head) = *list {
+            head.as_mut().prev = Some(slab_ptr);
+        }
+        *list = Some(slab_ptr);
+    }
+
+    /// Removes the slab from the given list.
+    ///
+    /// This function is unsafe because the slab must be part of that list.
+    unsafe fn remove(list: &mut Option<NonNull<Slab>>, mut slab_ptr: NonNull<Slab>) {
+        let slab = slab_ptr.as_mut();
+        match slab.prev {
+            Some(mut prev) => prev.as_mut().next = slab.next,
+            None => *list = slab.next,
+        }
+        if let Some(mut next) = slab.next {
+            next.as_mut().prev = slab.prev;
+        }
+        slab.next = None;
+        slab.prev = None;
+    }
+}
+
+/// A named cache of fixed-size slots for objects of type `T`.
+///
+/// Objects are packed into page-sized slabs instead of being rounded up to the next block size
+/// of the `FixedSizeBlockAllocator`, and a slab is given back to the heap as soon as its last
+/// object is freed, except for a single empty slab that every cache keeps. Caches are meant to be declared as statics:
+///
+/// ```ignore
+/// static TASK_CACHE: SlabCache<Task> = SlabCache::new("task");
+/// let task = TASK_CACHE.alloc(Task::new(example_task()));
+/// ```
+pub struct SlabCache<T> {
+    name: &'static str,
+    inner: Locked<RawSlabCache>,
+    // The cache never gives access to a `T` itself: `alloc` moves the value into a slot and hands it to the caller as a
+    // `SlabBox`, which is neither `Send` nor `Sync`. So the cache can be shared (and declared as a static) for any `T`,
+    // also for types like `Task` that are not `Sync`, and `fn() -> T` keeps `T` from restricting the auto traits.
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> SlabCache<T> {
+    /// Creates an empty cache. No memory is allocated until the first object is allocated.
+    pub const fn new(name: &'static str) -> Self {
+        SlabCache {
+            name,
+            inner: Locked::new(RawSlabCache::new(mem::size_of::<T>(), mem::align_of::<T>())),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the name that the cache was created with.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the current occupancy of the cache.
+    pub fn stats(&self) -> SlabCacheStats {
+        self.inner.lock().stats()
+    }
+
+    /// Calls `f` with the number of allocated objects of every slab that is not empty.
+    ///
+    /// The cache is locked while `f` runs, so `f` must not allocate from or free to this cache.
+    pub fn for_each_slab(&self, f: impl FnMut(usize)) {
+        self.inner.lock().for_each_slab(f)
+    }
+
+    /// Moves `value` into a slot of this cache.
+    ///
+    /// Returns the value again if no new slab could be allocated.
+    pub fn alloc(&self, value: T) -> Result<SlabBox<'_, T>, T> {
+        match self.inner.lock().alloc() {
+            Some(ptr) => {
+                let ptr = ptr.cast::<T>();
+                unsafe { ptr.as_ptr().write(value) };
+                Ok(SlabBox { ptr, cache: self })
+            }
+            None => Err(value),
+        }
+    }
+}
+
+/// An owned object in a `SlabCache`, similar to a `Box`.
+///
+/// The object is dropped and its slot is returned to the cache when the `SlabBox` is dropped.
+pub struct SlabBox<'a, T> {
+    ptr: NonNull<T>,
+    cache: &'a SlabCache<T>,
+}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            self.cache.inner.lock().dealloc(self.ptr.cast());
+        }
+    }
+}
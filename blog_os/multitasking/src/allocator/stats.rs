@@ -0,0 +1,93 @@
+use alloc::alloc::Layout;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::fixed_size_block::{SizeClassStats, BLOCK_SIZES};
+
+// The counters are updated by the `KernelAllocator` wrapper on every allocation, so they are the same for every selected allocator.
+// Atomics are enough here because every counter is updated independently.
+static HEAP_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static FAILED_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A snapshot of the kernel heap usage, as returned by `allocator::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Current size of the heap, including the pages mapped when it grew.
+    pub heap_bytes: usize,
+    /// Sum of the sizes of all live allocations.
+    pub allocated_bytes: usize,
+    /// Highest value that `allocated_bytes` ever had.
+    pub peak_bytes: usize,
+    /// Per-size-class usage, in the order of `BLOCK_SIZES`.
+    ///
+    /// Only filled in when the `FixedSizeBlockAllocator` is the global allocator.
+    pub size_classes: [SizeClassStats; BLOCK_SIZES.len()],
+    /// Number of allocations that were passed to the fallback allocator of the `FixedSizeBlockAllocator`.
+    pub fallback_allocations: usize,
+    /// Number of allocations that returned a null pointer.
+    pub failed_allocations: usize,
+}
+
+impl HeapStats {
+    /// Bytes of the heap that are not part of a live allocation.
+    ///
+    /// This includes memory lost to fragmentation and blocks sitting in the free lists of the `FixedSizeBlockAllocator`.
+    pub fn free_bytes(&self) -> usize {
+        self.heap_bytes.saturating_sub(self.allocated_bytes)
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "heap: {} bytes allocated, {} free, {} peak, {} total",
+            self.allocated_bytes,
+            self.free_bytes(),
+            self.peak_bytes,
+            self.heap_bytes
+        )?;
+        writeln!(
+            f,
+            "fallback allocations: {}, failed allocations: {}",
+            self.fallback_allocations, self.failed_allocations
+        )?;
+        for (size, class) in BLOCK_SIZES.iter().zip(self.size_classes.iter()) {
+            writeln!(f, "{:>5} bytes: {} in use, {} free", size, class.in_use, class.free)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the shared counters. The allocator-specific fields are left empty.
+pub(super) fn snapshot() -> HeapStats {
+    HeapStats {
+        heap_bytes: HEAP_BYTES.load(Ordering::Relaxed),
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        size_classes: [SizeClassStats::default(); BLOCK_SIZES.len()],
+        fallback_allocations: 0,
+        failed_allocations: FAILED_ALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+/// Records that the heap grew by `bytes`.
+pub(super) fn record_heap_growth(bytes: usize) {
+    HEAP_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Records the result of an allocation with the given layout.
+pub(super) fn record_alloc(layout: Layout, ptr: *mut u8) {
+    if ptr.is_null() {
+        FAILED_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    let allocated = ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+    PEAK_BYTES.fetch_max(allocated, Ordering::Relaxed);
+}
+
+/// Records that an allocation with the given layout was freed.
+pub(super) fn record_dealloc(layout: Layout) {
+    ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+}
@@ -0,0 +1,105 @@
+// Stack backtraces
+//
+// The kernel is compiled with frame pointers (see .cargo/config.toml), so every function starts with `push rbp; mov
+// rbp, rsp`. RBP therefore points to the saved RBP of the caller, and the return address into the caller is stored
+// right above it. Following the saved RBPs walks up the call stack.
+//
+// A corrupted stack can contain any value, so every frame is checked before it is read: it must be aligned, mapped and
+// above the previous one (the stack grows down). The walk stops at the first frame that fails these checks.
+
+use crate::{memory, println, serial_println, symbols};
+use core::arch::asm;
+use x86_64::VirtAddr;
+
+/// The walk stops after this many frames, in case the frame pointers form a very long chain.
+pub const MAX_FRAMES: usize = 64;
+
+/// Calls `f` with the return address of every frame, starting at the frame whose frame pointer is `rbp`.
+pub fn walk(rbp: u64, mut f: impl FnMut(VirtAddr)) {
+    let mut rbp = rbp;
+    for _ in 0..MAX_FRAMES {
+        if !frame_is_valid(rbp) {
+            break;
+        }
+        let (saved_rbp, return_address) = unsafe {
+            let frame = rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+        match VirtAddr::try_new(return_address) {
+            Ok(address) if return_address != 0 => f(address),
+            _ => break,
+        }
+        // the caller's frame is higher up on the stack
+        if saved_rbp <= rbp {
+            break;
+        }
+        rbp = saved_rbp;
+    }
+}
+
+/// Calls `f` with the return address of every frame of the caller and its callers.
+#[inline(never)]
+pub fn trace(f: impl FnMut(VirtAddr)) {
+    walk(current_rbp(), f);
+}
+
+/// Prints the backtrace of the caller to the screen and the serial port.
+#[inline(never)]
+pub fn print() {
+    println!("backtrace:");
+    serial_println!("backtrace:");
+    let mut index = 0;
+    walk(current_rbp(), |address| {
+        print_frame(index, address);
+        index += 1;
+    });
+}
+
+/// Prints the backtrace of interrupted code: `rip` is the instruction pointer from the interrupt stack frame and `rbp`
+/// the frame pointer at the time of the interrupt.
+pub fn print_from(rip: VirtAddr, rbp: u64) {
+    println!("backtrace:");
+    serial_println!("backtrace:");
+    print_frame(0, rip);
+    let mut index = 1;
+    walk(rbp, |address| {
+        print_frame(index, address);
+        index += 1;
+    });
+}
+
+fn print_frame(index: usize, address: VirtAddr) {
+    match symbols::lookup(address) {
+        Some(symbol) => {
+            println!("{:4}: {:#018x} {}", index, address.as_u64(), symbol);
+            serial_println!("{:4}: {:#018x} {}", index, address.as_u64(), symbol);
+        }
+        None => {
+            println!("{:4}: {:#018x}", index, address.as_u64());
+            serial_println!("{:4}: {:#018x}", index, address.as_u64());
+        }
+    }
+}
+
+/// Returns the frame pointer of the calling function.
+#[inline(always)]
+fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+/// Returns whether a frame (the saved RBP and the return address) can be read at `rbp`.
+fn frame_is_valid(rbp: u64) -> bool {
+    if rbp == 0 || !rbp.is_multiple_of(8) {
+        return false;
+    }
+    // the return address can be on the next page
+    let end = match rbp.checked_add(15) {
+        Some(end) => end,
+        None => return false,
+    };
+    [rbp, end]
+        .iter()
+        .all(|&address| VirtAddr::try_new(address).is_ok_and(memory::is_mapped))
+}
@@ -1,38 +1,40 @@
+use core::ptr::{addr_of, addr_of_mut};
 use lazy_static::lazy_static;
 use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor};
 use x86_64::structures::gdt::SegmentSelector;
+use crate::memory::stack;
+use crate::memory::vmalloc::VmallocError;
 
 
 // 我们将IST的0号位定义为 double fault 的专属栈（其他IST序号也可以如此施为）
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+// NMIs and machine checks can interrupt any code, even while the current stack is in a bad state, so they get their own stacks as well
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            
-            let stack_start = VirtAddr::from_ptr(unsafe {
-                &STACK
-            });
-            let stack_end   = stack_start + STACK_SIZE;
-            // 将栈的高地址指针写入0号位，之所以这样做，那是因为 x86 的栈内存分配是从高地址到低地址的
-            stack_end
-        };
-        tss
-    };
-    // 我们已经创建了一个TSS，现在的问题就是怎么让CPU使用它。不幸的是这事有点繁琐，因为TSS用到了分段系统（历史原因）。但我们可以不直接加载，而是在全局描述符表（GDT）中添加一个段描述符，然后我们就可以通过ltr 指令加上GDT序号加载我们的TSS。（这也是为什么我们将模块取名为 gdt。）
-}
+const IST_INDEXES: [u16; 3] = [DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX];
+
+/// The number of pages of every IST stack.
+pub const IST_STACK_PAGES: u64 = 5;
+
+// The IST stacks that are used until `init_stacks` replaces them. They are static, because the page tables cannot be
+// changed that early, so they have no guard page.
+const BOOT_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_STACKS: [[u8; BOOT_STACK_SIZE]; IST_INDEXES.len()] = [[0; BOOT_STACK_SIZE]; IST_INDEXES.len()];
+
+// The CPU reads the IST from the TSS on every interrupt, so `init_stacks` can replace the stacks after the TSS is loaded.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+// 我们已经创建了一个TSS，现在的问题就是怎么让CPU使用它。不幸的是这事有点繁琐，因为TSS用到了分段系统（历史原因）。但我们可以不直接加载，而是在全局描述符表（GDT）中添加一个段描述符，然后我们就可以通过ltr 指令加上GDT序号加载我们的TSS。（这也是为什么我们将模块取名为 gdt。）
 
 // GDT
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector  = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector  = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (gdt, Selectors { code_selector, tss_selector })
     };
 }
@@ -49,10 +51,39 @@ pub fn init() {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, Segment};
 
+    for (i, &index) in IST_INDEXES.iter().enumerate() {
+        let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(BOOT_STACKS[i]) });
+        // 将栈的高地址指针写入IST，之所以这样做，那是因为 x86 的栈内存分配是从高地址到低地址的
+        set_ist_stack(index, stack_start + BOOT_STACK_SIZE);
+    }
+
     GDT.0.load();
     unsafe {
         // 我们通过 set_reg 覆写了代码段寄存器(cs)，然后使用 load_tss 来重载了TSS
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
-}
\ No newline at end of file
+}
+
+/// Replaces the static IST stacks with stacks from `memory::stack`, which have a guard page.
+///
+/// Needs the global mapper, so it must be called after `memory::init_global`.
+pub fn init_stacks() -> Result<(), VmallocError> {
+    for &index in IST_INDEXES.iter() {
+        let stack = stack::allocate_stack(IST_STACK_PAGES)?;
+        set_ist_stack(index, stack.top());
+    }
+    Ok(())
+}
+
+/// Returns the top of the stack that the CPU switches to for the given IST index.
+pub fn ist_stack(index: u16) -> VirtAddr {
+    unsafe { (*addr_of!(TSS)).interrupt_stack_table[usize::from(index)] }
+}
+
+fn set_ist_stack(index: u16, top: VirtAddr) {
+    // an exception that uses the entry must not see a half-written value
+    interrupts::without_interrupts(|| unsafe {
+        (*addr_of_mut!(TSS)).interrupt_stack_table[usize::from(index)] = top;
+    });
+}
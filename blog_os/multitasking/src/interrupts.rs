@@ -1,12 +1,15 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
-use crate::{println, print, gdt};
+use crate::println;
 // static mut is prone to data races
 use lazy_static::lazy_static;
 // intel 8259 programmable interrupt controller (PIC)
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::PageFaultErrorCode;
-use crate::hlt_loop;
+
+// local APIC and I/O APIC, which replace the PICs when `apic::init` is called
+pub mod apic;
+// handlers for the CPU exceptions that dump the registers
+pub mod exceptions;
 
 // 将PIC的中断编号范围设定为了32–47
 pub const PIC_1_OFFSET: u8 = 32;
@@ -24,6 +27,14 @@ pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     // 键盘使用的是主PIC的1号管脚，在CPU的中断编号为33（1 + 偏移量32）。我们需要在 InterruptIndex 枚举类型里添加一个 Keyboard，但是无需显式指定对应值，因为在默认情况下，它的对应值是上一个枚举对应值加一也就是33。
     Keyboard,
+    // COM1 uses IRQ 4
+    Serial = PIC_1_OFFSET + 4,
+    // the PICs raise IRQ 7 and 15 for spurious interrupts, which must not be acknowledged
+    PicSpurious1 = PIC_1_OFFSET + 7,
+    PicSpurious2 = PIC_2_OFFSET + 7,
+    // vectors of the local APIC, which have no IRQ number
+    ApicError = 0xfe,
+    ApicSpurious = 0xff,
 }
 
 impl InterruptIndex {
@@ -40,21 +51,24 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        // double fault (idx: 8) handler
-        unsafe {
-            // 在IDT中为 double fault 对应的处理函数设置栈序号
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        }
+        // all other exceptions, including the double fault (idx: 8), which runs on its own stack, and the page fault
+        exceptions::set_handlers(&mut idt);
 
         // InterruptDescriptorTable 结构实现了 IndexMut trait，所以我们可以通过序号来单独修改某一个条目。
         idt[InterruptIndex::Timer.as_usize()]
             .set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
-        
-        // register the page fault handler
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt[InterruptIndex::Serial.as_usize()]
+            .set_handler_fn(serial_interrupt_handler);
+        idt[InterruptIndex::PicSpurious1.as_usize()]
+            .set_handler_fn(spurious_interrupt_handler);
+        idt[InterruptIndex::PicSpurious2.as_usize()]
+            .set_handler_fn(spurious_interrupt_handler);
+        idt[InterruptIndex::ApicError.as_usize()]
+            .set_handler_fn(apic_error_handler);
+        idt[InterruptIndex::ApicSpurious.as_usize()]
+            .set_handler_fn(spurious_interrupt_handler);
 
         idt
     };
@@ -79,23 +93,14 @@ extern "x86-interrupt" fn  breakpoint_handler(
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
-// double fault exception handler
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame, _error_code: u64) -> !
-{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
-}
-
 // timer interrupt handler
 extern "x86-interrupt" fn timer_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
-    print!(".");
+    // advance the clock of the `time` module; sleeping tasks are woken by the executor, not by the interrupt handler
+    crate::time::tick();
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Timer);
 }
 
 /// keyboard interrupt handler
@@ -115,24 +120,45 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     // call `add_scancode` (in task/keyboard.rs) on keyboard interrupts
     crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+/// serial interrupt handler
+///
+/// COM1 raises an interrupt for every received byte. The byte is echoed to the screen.
+/// The 8259 PIC keeps IRQ 4 masked, so this handler is only used with the APIC.
+extern "x86-interrupt" fn serial_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    let byte = crate::serial::SERIAL1.lock().receive();
+    crate::print!("{}", byte as char);
+
+    end_of_interrupt(InterruptIndex::Serial);
 }
 
-// page fault handler
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode)
+// spurious interrupts are not real interrupts, so they must not be acknowledged
+extern "x86-interrupt" fn spurious_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
 {
-    // CR2 寄存器会在 page fault 发生时，被CPU自动写入导致异常的虚拟地址
-    use x86_64::registers::control::Cr2;
-
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+}
+
+extern "x86-interrupt" fn apic_error_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    println!("WARNING: local APIC error");
+    end_of_interrupt(InterruptIndex::ApicError);
+}
+
+/// Signals the end of the given interrupt to the interrupt controller that raised it.
+fn end_of_interrupt(index: InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(index.as_u8());
+        }
+    }
 }
 
 /// create a test_breakpoint_exception test
@@ -140,4 +166,11 @@ extern "x86-interrupt" fn page_fault_handler(
 fn test_breakpoint_exception() {
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
+}
+
+/// the debug exception handler returns through the exception stub
+#[test_case]
+fn test_debug_exception() {
+    // `int1` raises a debug exception
+    unsafe { core::arch::asm!("int1") };
 }
\ No newline at end of file
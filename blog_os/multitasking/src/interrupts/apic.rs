@@ -0,0 +1,266 @@
+// Local APIC and I/O APIC
+//
+// The 8259 PICs only support a single CPU. With the APIC, every CPU has a local APIC that receives its interrupts and has
+// its own timer, and the I/O APIC routes the external interrupts (global system interrupts, GSIs) to the local APICs.
+// `init` finds both in the MADT, masks the 8259 PICs and keeps the vectors of `InterruptIndex`, so the interrupt
+// handlers work the same with both interrupt controllers.
+//
+// The registers are memory-mapped. They are mapped uncached with `memory::map_mmio`.
+
+use super::{InterruptIndex, PICS};
+use crate::acpi::{self, madt::Signal, AcpiError, Madt};
+use crate::memory::{self, vmalloc::VmallocError, MmioRegion};
+use crate::{pit, time};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+// local APIC registers
+const ID: usize = 0x20;
+const EOI: usize = 0xb0;
+const SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+const LVT_TIMER: usize = 0x320;
+const LVT_ERROR: usize = 0x370;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE_CONFIGURATION: usize = 0x3e0;
+// size of the mapped register range
+const LOCAL_APIC_SIZE: usize = 0x400;
+
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+// divide the bus clock by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+// I/O APIC registers, accessed indirectly through a register select and a data window
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+const IO_APIC_SIZE: usize = 0x20;
+
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+/// Number of PIT ticks over which the local APIC timer frequency is measured.
+const CALIBRATION_TICKS: u64 = 50;
+
+/// The ISA interrupts that are routed through the I/O APIC.
+const ISA_ROUTES: [(u8, InterruptIndex); 2] = [(1, InterruptIndex::Keyboard), (4, InterruptIndex::Serial)];
+
+// the registers of the local APIC, uninitialized as long as the APIC is not used
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+// frequency of the local APIC timer (after the divider) in Hz
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether the APIC has replaced the 8259 PICs.
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.is_initialized()
+}
+
+/// An error while switching to the APIC.
+#[derive(Debug)]
+pub enum ApicError {
+    /// The MADT is missing or the ACPI tables are invalid.
+    Acpi(AcpiError),
+    /// The registers could not be mapped.
+    Mmio(VmallocError),
+}
+
+/// Switches from the 8259 PICs to the APIC.
+///
+/// The local APIC timer replaces the PIT as the source of `InterruptIndex::Timer`. Its frequency is measured against
+/// the PIT, so this must be called after `blog_os::init`, with interrupts enabled. If the MADT cannot be found, the
+/// PICs stay in use and the error is returned.
+///
+/// The registers are mapped with `memory::map_mmio`, so `memory::init_global` must be called first.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that the complete physical memory is mapped to virtual
+/// memory at the passed `physical_memory_offset`. Also, this function must be only called once, since it reprograms
+/// the interrupt controllers and the timer while the interrupt handlers keep using the registers of the first call.
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> Result<(), ApicError> {
+    let madt = acpi::init(physical_memory_offset)
+        .map_err(ApicError::Acpi)?
+        .madt
+        .as_ref()
+        .ok_or(ApicError::Acpi(AcpiError::TableNotFound(Madt::SIGNATURE)))?;
+    let local_apic = LocalApic::new(madt.local_apic_address).map_err(ApicError::Mmio)?;
+    // the I/O APICs are only needed to set up the routing, they are unmapped at the end
+    let io_apics = madt
+        .io_apics
+        .iter()
+        .map(|entry| IoApic::new(entry.address))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApicError::Mmio)?;
+
+    // 1. enable the local APIC and let it deliver spurious interrupts to their own vector
+    let mut apic_base = Msr::new(IA32_APIC_BASE_MSR);
+    apic_base.write(apic_base.read() | APIC_BASE_ENABLE);
+    local_apic.write(
+        SPURIOUS_INTERRUPT_VECTOR,
+        APIC_SOFTWARE_ENABLE | u32::from(InterruptIndex::ApicSpurious.as_u8()),
+    );
+    local_apic.write(LVT_ERROR, u32::from(InterruptIndex::ApicError.as_u8()));
+
+    // 2. measure the timer frequency while the PIT still raises the timer interrupt
+    let timer_frequency = local_apic.calibrate_timer();
+    TIMER_FREQUENCY.store(timer_frequency, Ordering::Relaxed);
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // 3. mask all interrupts of the 8259 PICs
+        if madt.has_8259_pics {
+            PICS.lock().disable();
+        }
+
+        // 4. route the legacy interrupts through the I/O APIC to this CPU
+        let apic_id = (local_apic.read(ID) >> 24) as u8;
+        route_isa_interrupts(madt, &io_apics, apic_id);
+
+        // 5. let the local APIC timer drive the clock
+        local_apic.write(LVT_TIMER, LVT_TIMER_PERIODIC | u32::from(InterruptIndex::Timer.as_u8()));
+        // on a second call, the registers stay mapped at the address of the first call
+        let _ = LOCAL_APIC.try_init_once(|| local_apic);
+        time::set_tick_source(time::TickSource::LocalApic);
+    });
+    Ok(())
+}
+
+/// Signals the end of an interrupt to the local APIC.
+pub(crate) fn end_of_interrupt() {
+    local_apic().write(EOI, 0);
+}
+
+/// Programs the local APIC timer to fire `frequency` times per second.
+///
+/// Returns the number of timer cycles per interrupt and the frequency of the timer.
+pub(crate) fn set_timer_frequency(frequency: u32) -> (u64, u64) {
+    let timer_frequency = TIMER_FREQUENCY.load(Ordering::Relaxed);
+    let count = ((timer_frequency + u64::from(frequency) / 2) / u64::from(frequency.max(1))).clamp(1, u64::from(u32::MAX));
+    local_apic().write(TIMER_INITIAL_COUNT, count as u32);
+    (count, timer_frequency)
+}
+
+fn local_apic() -> &'static LocalApic {
+    LOCAL_APIC.get().expect("the local APIC is not initialized")
+}
+
+/// Routes the interrupts in `ISA_ROUTES` to the local APIC with the given ID and masks all other I/O APIC inputs.
+///
+/// `io_apics` are the I/O APICs of the MADT, in the same order.
+fn route_isa_interrupts(madt: &Madt, io_apics: &[IoApic], apic_id: u8) {
+    for io_apic in io_apics.iter() {
+        for input in 0..io_apic.redirection_entries() {
+            io_apic.set_redirection(input, REDIRECTION_MASKED, 0);
+        }
+    }
+
+    for &(irq, index) in ISA_ROUTES.iter() {
+        let (gsi, signal) = madt.isa_irq(irq);
+        let entry = madt
+            .io_apics
+            .iter()
+            .zip(io_apics)
+            .find(|(entry, io_apic)| gsi >= entry.gsi_base && gsi - entry.gsi_base < io_apic.redirection_entries());
+        if let Some((entry, io_apic)) = entry {
+            io_apic.set_redirection(gsi - entry.gsi_base, redirection_flags(index, signal), apic_id);
+        }
+    }
+}
+
+fn redirection_flags(index: InterruptIndex, signal: Signal) -> u32 {
+    // fixed delivery mode to a physical destination
+    let mut flags = u32::from(index.as_u8());
+    if signal.active_low {
+        flags |= REDIRECTION_ACTIVE_LOW;
+    }
+    if signal.level_triggered {
+        flags |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+    flags
+}
+
+struct LocalApic {
+    registers: MmioRegion,
+}
+
+impl LocalApic {
+    /// This function is unsafe because `address` must be the address of the registers, as found in the MADT.
+    unsafe fn new(address: PhysAddr) -> Result<LocalApic, VmallocError> {
+        Ok(LocalApic {
+            registers: memory::map_mmio(address, LOCAL_APIC_SIZE)?,
+        })
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        self.registers.read(register)
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        self.registers.write(register, value)
+    }
+
+    /// Returns the number of timer cycles per second, measured over `CALIBRATION_TICKS` ticks of the PIT.
+    fn calibrate_timer(&self) -> u64 {
+        self.write(TIMER_DIVIDE_CONFIGURATION, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER, LVT_MASKED);
+
+        // start at the beginning of a tick, so that whole ticks are measured
+        let start = time::ticks();
+        while time::ticks() == start {
+            x86_64::instructions::hlt();
+        }
+        self.write(TIMER_INITIAL_COUNT, u32::MAX);
+        let start = time::ticks();
+        while time::ticks() < start + CALIBRATION_TICKS {
+            x86_64::instructions::hlt();
+        }
+        let elapsed = u64::from(u32::MAX - self.read(TIMER_CURRENT_COUNT));
+        self.write(TIMER_INITIAL_COUNT, 0);
+
+        // the measured time is CALIBRATION_TICKS * divisor / BASE_FREQUENCY seconds
+        elapsed * u64::from(pit::BASE_FREQUENCY) / (CALIBRATION_TICKS * u64::from(pit::divisor()))
+    }
+}
+
+struct IoApic {
+    registers: MmioRegion,
+}
+
+impl IoApic {
+    /// This function is unsafe because `address` must be the address of the registers, as found in the MADT.
+    unsafe fn new(address: PhysAddr) -> Result<IoApic, VmallocError> {
+        Ok(IoApic {
+            registers: memory::map_mmio(address, IO_APIC_SIZE)?,
+        })
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        self.registers.write(IOREGSEL, register);
+        self.registers.read(IOWIN)
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        self.registers.write(IOREGSEL, register);
+        self.registers.write(IOWIN, value);
+    }
+
+    /// Returns the number of inputs of the I/O APIC.
+    fn redirection_entries(&self) -> u32 {
+        ((self.read(IOAPICVER) >> 16) & 0xff) + 1
+    }
+
+    fn set_redirection(&self, input: u32, flags: u32, apic_id: u8) {
+        // the destination APIC ID is in the upper 8 bits of the high half
+        self.write(IOREDTBL + 2 * input + 1, u32::from(apic_id) << 24);
+        self.write(IOREDTBL + 2 * input, flags);
+    }
+}
@@ -0,0 +1,352 @@
+// CPU exceptions
+//
+// The `x86-interrupt` handlers in `interrupts` only see the interrupt stack frame, and the general purpose registers are
+// already changed when their body runs. The handlers of this module therefore start with a small assembly stub that
+// saves all registers on the stack before any Rust code runs. Together with the error code (0 if the exception has
+// none), the vector number and the interrupt stack frame that the CPU pushed, they form an `ExceptionContext`, which
+// `exception_handler` decodes and dumps before it panics.
+//
+// Only debug exceptions and page faults in demand-paged regions return: the stub restores the registers and returns
+// with `iretq` like an `x86-interrupt` handler. Breakpoints keep their `x86-interrupt` handler.
+
+use crate::{backtrace, gdt, hlt_loop, memory, println, symbols};
+use core::arch::{asm, global_asm};
+use core::fmt;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrameValue, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+// machine check architecture
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MC0_STATUS: u32 = 0x401;
+const MC_STATUS_VALID: u64 = 1 << 63;
+
+// Every stub pushes the error code (if the CPU did not) and the vector, then jumps to the common part. The common part
+// pushes the general purpose registers in the reverse order of `Registers` and passes the resulting `ExceptionContext`
+// to `exception_handler`. Below the context, it pushes a stack frame for the faulting instruction (its address and the
+// interrupted RBP), so that a backtrace from the handler includes the function in which the exception happened. With
+// the 24 values on the stack, the stack is 16-byte aligned for the call, as the CPU aligns the stack before it pushes
+// the interrupt stack frame.
+global_asm!(
+    ".macro exception_stub vector, has_error_code",
+    ".global exception_stub_\\vector",
+    "exception_stub_\\vector:",
+    ".if \\has_error_code == 0",
+    "    push 0",
+    ".endif",
+    "    push \\vector",
+    "    jmp exception_common",
+    ".endm",
+    "exception_stub 0, 0",
+    "exception_stub 1, 0",
+    "exception_stub 2, 0",
+    "exception_stub 4, 0",
+    "exception_stub 5, 0",
+    "exception_stub 6, 0",
+    "exception_stub 7, 0",
+    "exception_stub 8, 1",
+    "exception_stub 10, 1",
+    "exception_stub 11, 1",
+    "exception_stub 12, 1",
+    "exception_stub 13, 1",
+    "exception_stub 14, 1",
+    "exception_stub 16, 0",
+    "exception_stub 17, 1",
+    "exception_stub 18, 0",
+    "exception_stub 19, 0",
+    "exception_stub 20, 0",
+    "exception_stub 21, 1",
+    "exception_stub 28, 0",
+    "exception_stub 29, 1",
+    "exception_stub 30, 1",
+    "exception_common:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    // the instruction pointer is behind the registers, the vector and the error code
+    "    push qword ptr [rsp + 17 * 8]",
+    "    push rbp",
+    "    mov rbp, rsp",
+    "    cld",
+    "    call {handler}",
+    "    add rsp, 16",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // remove the vector and the error code
+    "    add rsp, 16",
+    "    iretq",
+    handler = sym exception_handler,
+);
+
+extern "C" {
+    fn exception_stub_0();
+    fn exception_stub_1();
+    fn exception_stub_2();
+    fn exception_stub_4();
+    fn exception_stub_5();
+    fn exception_stub_6();
+    fn exception_stub_7();
+    fn exception_stub_8();
+    fn exception_stub_10();
+    fn exception_stub_11();
+    fn exception_stub_12();
+    fn exception_stub_13();
+    fn exception_stub_14();
+    fn exception_stub_16();
+    fn exception_stub_17();
+    fn exception_stub_18();
+    fn exception_stub_19();
+    fn exception_stub_20();
+    fn exception_stub_21();
+    fn exception_stub_28();
+    fn exception_stub_29();
+    fn exception_stub_30();
+}
+
+/// The general purpose registers at the time of the exception.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// Everything that the exception stubs save, in the order in which it is on the stack.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionContext {
+    pub registers: Registers,
+    pub vector: u64,
+    /// The error code pushed by the CPU, or 0 for exceptions without an error code.
+    pub error_code: u64,
+    pub stack_frame: InterruptStackFrameValue,
+}
+
+/// Installs the exception stubs in the IDT.
+///
+/// Double faults, NMIs and machine checks run on their own stacks (see `gdt`).
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.divide_error.set_handler_addr(addr(exception_stub_0));
+        idt.debug.set_handler_addr(addr(exception_stub_1));
+        idt.non_maskable_interrupt
+            .set_handler_addr(addr(exception_stub_2))
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.overflow.set_handler_addr(addr(exception_stub_4));
+        idt.bound_range_exceeded.set_handler_addr(addr(exception_stub_5));
+        idt.invalid_opcode.set_handler_addr(addr(exception_stub_6));
+        idt.device_not_available.set_handler_addr(addr(exception_stub_7));
+        idt.double_fault
+            .set_handler_addr(addr(exception_stub_8))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss.set_handler_addr(addr(exception_stub_10));
+        idt.segment_not_present.set_handler_addr(addr(exception_stub_11));
+        idt.stack_segment_fault.set_handler_addr(addr(exception_stub_12));
+        idt.general_protection_fault.set_handler_addr(addr(exception_stub_13));
+        idt.page_fault.set_handler_addr(addr(exception_stub_14));
+        idt.x87_floating_point.set_handler_addr(addr(exception_stub_16));
+        idt.alignment_check.set_handler_addr(addr(exception_stub_17));
+        idt.machine_check
+            .set_handler_addr(addr(exception_stub_18))
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        idt.simd_floating_point.set_handler_addr(addr(exception_stub_19));
+        idt.virtualization.set_handler_addr(addr(exception_stub_20));
+        idt.cp_protection_exception.set_handler_addr(addr(exception_stub_21));
+        idt.hv_injection_exception.set_handler_addr(addr(exception_stub_28));
+        idt.vmm_communication_exception.set_handler_addr(addr(exception_stub_29));
+        idt.security_exception.set_handler_addr(addr(exception_stub_30));
+    }
+}
+
+fn addr(stub: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(stub as usize as u64)
+}
+
+/// Called by the exception stubs.
+extern "C" fn exception_handler(context: &mut ExceptionContext) {
+    let vector = context.vector as u8;
+    if vector == 1 {
+        // debug exceptions are raised by breakpoints in the debug registers and by single-stepping, both of which can continue
+        crate::println!("EXCEPTION: DEBUG\n{:#?}", context.stack_frame);
+        return;
+    }
+    if vector == 14 {
+        page_fault(context);
+        return;
+    }
+    panic!("EXCEPTION: {}{}\n{}", name(vector), ErrorCode(context), context);
+}
+
+/// Maps the page if the fault is in a demand-paged region, otherwise prints the fault and halts.
+fn page_fault(context: &ExceptionContext) {
+    // CR2 寄存器会在 page fault 发生时，被CPU自动写入导致异常的虚拟地址
+    let address = Cr2::read();
+    let error_code = PageFaultErrorCode::from_bits_truncate(context.error_code);
+    // pages of demand-paged regions are mapped on their first access, and the instruction is retried
+    if memory::region::handle_page_fault(address, error_code) {
+        return;
+    }
+
+    println!("EXCEPTION: PAGE FAULT");
+    println!("Accessed Address: {:?}", address);
+    println!("Error Code: {:?}", error_code);
+    println!("{}", context);
+    backtrace::print_from(context.stack_frame.instruction_pointer, context.registers.rbp);
+    hlt_loop();
+}
+
+/// Returns the name of the exception with the given vector.
+pub fn name(vector: u8) -> &'static str {
+    match vector {
+        0 => "DIVIDE ERROR",
+        1 => "DEBUG",
+        2 => "NON-MASKABLE INTERRUPT",
+        3 => "BREAKPOINT",
+        4 => "OVERFLOW",
+        5 => "BOUND RANGE EXCEEDED",
+        6 => "INVALID OPCODE",
+        7 => "DEVICE NOT AVAILABLE",
+        8 => "DOUBLE FAULT",
+        10 => "INVALID TSS",
+        11 => "SEGMENT NOT PRESENT",
+        12 => "STACK-SEGMENT FAULT",
+        13 => "GENERAL PROTECTION FAULT",
+        14 => "PAGE FAULT",
+        16 => "X87 FLOATING-POINT EXCEPTION",
+        17 => "ALIGNMENT CHECK",
+        18 => "MACHINE CHECK",
+        19 => "SIMD FLOATING-POINT EXCEPTION",
+        20 => "VIRTUALIZATION EXCEPTION",
+        21 => "CONTROL PROTECTION EXCEPTION",
+        28 => "HYPERVISOR INJECTION EXCEPTION",
+        29 => "VMM COMMUNICATION EXCEPTION",
+        30 => "SECURITY EXCEPTION",
+        _ => "UNKNOWN EXCEPTION",
+    }
+}
+
+/// Formats the error code of an exception and other state that explains it, starting with a separator.
+struct ErrorCode<'a>(&'a ExceptionContext);
+
+impl fmt::Display for ErrorCode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.0.error_code;
+        match self.0.vector {
+            // selector error code: bit 0 is set if the exception was caused by an external event, bits 1-2 select
+            // the descriptor table and bits 3-15 are the index of the descriptor
+            10..=13 if code == 0 => Ok(()),
+            10..=13 => {
+                let table = match (code >> 1) & 0b11 {
+                    0b00 => "GDT",
+                    0b10 => "LDT",
+                    _ => "IDT",
+                };
+                write!(f, ": selector {:#x} in the {}", (code >> 3) & 0x1fff, table)?;
+                if code & 1 != 0 {
+                    write!(f, " (external event)")?;
+                }
+                Ok(())
+            }
+            // the exception flags of the MXCSR and the x87 status word show which operation failed
+            19 => write!(f, ": MXCSR {:#x}", mxcsr()),
+            16 => write!(f, ": FSW {:#x}", x87_status_word()),
+            18 => write_machine_check(f),
+            // the CPU cannot push the exception frame for a page fault in the guard page of a full stack
+            8 if memory::stack::is_guard_page(Cr2::read()) => write!(f, ": stack overflow"),
+            17 | 21 | 29 | 30 => write!(f, ": error code {:#x}", code),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes the machine check status and the valid error banks.
+fn write_machine_check(f: &mut fmt::Formatter) -> fmt::Result {
+    // the machine check MSRs exist if CPUID reports the machine check architecture (leaf 1, EDX bit 14)
+    let features = core::arch::x86_64::__cpuid(1);
+    if features.edx & (1 << 14) == 0 {
+        return Ok(());
+    }
+    unsafe {
+        write!(f, ": MCG_STATUS {:#x}", Msr::new(IA32_MCG_STATUS).read())?;
+        let banks = Msr::new(IA32_MCG_CAP).read() & 0xff;
+        for bank in 0..banks as u32 {
+            let status = Msr::new(IA32_MC0_STATUS + 4 * bank).read();
+            if status & MC_STATUS_VALID != 0 {
+                write!(f, ", MC{}_STATUS {:#x}", bank, status)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn mxcsr() -> u32 {
+    let mut mxcsr: u32 = 0;
+    unsafe { asm!("stmxcsr [{}]", in(reg) &mut mxcsr, options(nostack)) };
+    mxcsr
+}
+
+fn x87_status_word() -> u16 {
+    let status: u16;
+    unsafe { asm!("fnstsw ax", out("ax") status, options(nomem, nostack)) };
+    status
+}
+
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.registers;
+        let frame = &self.stack_frame;
+        writeln!(f, "RAX={:016x} RBX={:016x} RCX={:016x}", r.rax, r.rbx, r.rcx)?;
+        writeln!(f, "RDX={:016x} RSI={:016x} RDI={:016x}", r.rdx, r.rsi, r.rdi)?;
+        writeln!(f, "RBP={:016x} RSP={:016x} R8 ={:016x}", r.rbp, frame.stack_pointer.as_u64(), r.r8)?;
+        writeln!(f, "R9 ={:016x} R10={:016x} R11={:016x}", r.r9, r.r10, r.r11)?;
+        writeln!(f, "R12={:016x} R13={:016x} R14={:016x}", r.r12, r.r13, r.r14)?;
+        writeln!(f, "R15={:016x} RIP={:016x} RFLAGS={:08x}", r.r15, frame.instruction_pointer.as_u64(), frame.cpu_flags)?;
+        writeln!(f, "CS={:04x} SS={:04x} CR0={:08x} CR2={:016x}", frame.code_segment, frame.stack_segment, Cr0::read_raw(), Cr2::read().as_u64())?;
+        write!(f, "CR3={:016x} CR4={:08x}", Cr3::read().0.start_address().as_u64(), Cr4::read_raw())?;
+        if let Some(symbol) = symbols::lookup(frame.instruction_pointer) {
+            write!(f, "\nin {}", symbol)?;
+        }
+        Ok(())
+    }
+}
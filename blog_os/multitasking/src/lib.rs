@@ -7,6 +7,8 @@
 #![feature(abi_x86_interrupt)]
 // specifies a function that is called when an allocation error occurs
 #![feature(alloc_error_handler)]
+// fallible allocation through `Box::try_new` and `Arc::try_new`
+#![feature(allocator_api)]
 // enable the use of mutable references in const functions
 #![feature(const_mut_refs)]
 
@@ -14,18 +16,30 @@
 // The first step in implementing a heap allocator is to add a dependency on the built-in alloc crate. Like the core crate, it is a subset of the standard library that additionally contains the allocation and collection types. 
 extern crate alloc;
 
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
 
 pub mod serial;
 pub mod vga_buffer;
 pub mod interrupts;
+// programmable interval timer and the monotonic clock based on it
+pub mod pit;
+pub mod time;
 // create a new TSS that contains a separate double fault stack in its interrupt stack table.
 pub mod gdt;
 // implement page table 
 pub mod memory;
+// ACPI tables describing the platform
+pub mod acpi;
+// shutdown and reboot
+pub mod power;
 // dynamic meory allocator
 pub mod allocator;
 pub mod task;
+// stack backtraces through the frame pointers
+pub mod backtrace;
+// names of the kernel functions, embedded after linking
+pub mod symbols;
 
 pub trait Testable {
     fn run(&self) -> ();
@@ -53,10 +67,74 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    backtrace::print();
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
 
+/// Initializes the kernel for integration tests that need the heap.
+///
+/// Like `main`, it runs `init`, creates the mapper and the frame allocator, maps the heap and hands the mapper and the
+/// frame allocator over to `memory::init_global`, so that the heap can grow.
+pub fn test_init(boot_info: &'static BootInfo) {
+    use memory::buddy::BuddyFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+}
+
+/// Panic handler for tests that end in a panic: the test passes if the panic message starts with `expected`.
+///
+/// The message is compared while it is formatted, so that no heap is needed.
+pub fn expect_panic_message(info: &PanicInfo, expected: &str) -> ! {
+    use core::fmt::Write;
+
+    let mut check = StartsWith {
+        expected: expected.as_bytes(),
+        matched: 0,
+        mismatch: false,
+    };
+    write!(check, "{}", info.message()).unwrap();
+    if check.mismatch || check.matched < expected.len() {
+        serial_println!("[failed]\n\nError: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    } else {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    }
+    hlt_loop();
+}
+
+/// Checks whether the formatted text starts with `expected`.
+struct StartsWith<'a> {
+    expected: &'a [u8],
+    matched: usize,
+    mismatch: bool,
+}
+
+impl core::fmt::Write for StartsWith<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            if self.matched == self.expected.len() {
+                break;
+            }
+            if self.expected[self.matched] != byte {
+                self.mismatch = true;
+            }
+            self.matched += 1;
+        }
+        Ok(())
+    }
+}
+
 // 让CPU在下一个中断触发之前休息一下，也就是进入休眠状态来节省一点点能源。[hlt instruction][hlt 指令] 可以让我们做到这一点
 pub fn hlt_loop() -> ! {
     loop {
@@ -102,6 +180,8 @@ pub fn init() {
     interrupts::init_idt();
     // 我们使用 initialize 函数进行 8259 PIC 的初始化。正如 ChainedPics::new ，这个函数也是 unsafe 的，因为里面的不安全逻辑可能会导致PIC配置失败，进而出现一些未定义行为。
     unsafe { interrupts::PICS.lock().initialize() };
+    // program the timer interrupt rate before interrupts are enabled
+    time::init();
     // 启用中断
     x86_64::instructions::interrupts::enable();
     // x86_64 crate 中的 interrupts::enable 会执行特殊的 sti (“set interrupts”) 指令来启用外部中断。当我们试着执行 cargo run 后，double fault 异常几乎是立刻就被抛出了
@@ -111,7 +191,7 @@ pub fn init() {
 
 // Since the entry point is only used in test mode, we add the #[cfg(test)] attribute to all items. We give our test entry point the distinct name test_kernel_main to avoid confusion with the kernel_main of our main.rs. We don’t use the BootInfo parameter for now, so we prefix the parameter name with a _ to silence the unused variable warning.
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
@@ -127,5 +207,6 @@ fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("allocation error: {:?}", layout)
+    // the allocator already tried to free cached memory, so the heap is really exhausted
+    panic!("allocation error: {:?}\n{}", layout, allocator::stats())
 }
\ No newline at end of file
@@ -30,13 +30,22 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut mapper = unsafe {
         memory::init(phys_mem_offset)
     };
-    // create the mapping with BooInfoFrameAllocator
+    // create the frame allocator; unlike BootInfoFrameAllocator, it can take frames back and hand out contiguous blocks
     let mut frame_allocator = unsafe {
-        memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
+        memory::buddy::BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
     };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    // hand the mapper and frame allocator over so that the heap can grow on demand
+    memory::init_global(mapper, frame_allocator);
+    // move the IST stacks to mapped stacks with guard pages
+    blog_os::gdt::init_stacks().expect("IST stack allocation failed");
+
+    // switch from the 8259 PICs to the APIC; the PICs stay in use if there is no MADT
+    if let Err(err) = unsafe { blog_os::interrupts::apic::init(phys_mem_offset) } {
+        println!("WARNING: APIC unavailable ({:?}), using the 8259 PICs", err);
+    }
 
     // 1. a new instance of our Executor type is created
     let mut executor = Executor::new();
@@ -53,6 +62,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
+    blog_os::backtrace::print();
     blog_os::hlt_loop();
 }
 
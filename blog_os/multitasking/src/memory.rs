@@ -20,6 +20,29 @@ use x86_64::structures::paging::OffsetPageTable;
 use bootloader::bootinfo::MemoryMap;
 use bootloader::bootinfo::MemoryRegionType;
 
+// bitmap-based frame allocator that also supports deallocation
+pub mod bitmap;
+// buddy-system frame allocator for physically contiguous allocations
+pub mod buddy;
+// kernel stacks with guard pages
+pub mod stack;
+// registry of virtual memory regions, used for demand paging
+pub mod region;
+// allocator for ranges of the kernel address space
+pub mod vmalloc;
+// uncached mappings of device registers
+pub mod mmio;
+
+pub use mmio::{map_mmio, MmioRegion};
+
+use buddy::BuddyFrameAllocator;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::PageTableFlags;
+
+// the offset passed to `init`, 0 before that
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -29,6 +52,7 @@ use bootloader::bootinfo::MemoryRegionType;
 pub unsafe fn init(physical_memory_offset: VirtAddr) 
     -> OffsetPageTable<'static> 
 {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     // returns a new OffsetPageTable instance with a 'static lifetime.
     // This means that the instance stays valid for the complete runtime of our kernel.
@@ -36,6 +60,75 @@ pub unsafe fn init(physical_memory_offset: VirtAddr)
 }
 
 
+/// The kernel's page table and frame allocator after boot.
+///
+/// Code that needs to map memory at an arbitrary time (for example the heap when it grows)
+/// cannot receive the mapper and frame allocator as arguments, so `kernel_main` hands them
+/// over through `init_global` once it no longer needs them itself. The frame allocator is a
+/// `BuddyFrameAllocator`, so that physically contiguous blocks (e.g. for DMA buffers or 2 MiB
+/// pages) come from the same pool of frames as single frames.
+static GLOBAL_MAPPER: spin::Mutex<Option<(OffsetPageTable<'static>, BuddyFrameAllocator)>> =
+    spin::Mutex::new(None);
+
+/// Makes the given mapper and frame allocator available through `with_global`.
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    interrupts::without_interrupts(|| {
+        *GLOBAL_MAPPER.lock() = Some((mapper, frame_allocator));
+    });
+}
+
+/// Runs `f` with the global mapper and frame allocator.
+///
+/// Returns `None` if `init_global` was not called yet or if the mapper is already in use.
+/// The latter happens when `f` allocates on the heap and the heap needs to grow, or when an
+/// interrupt handler needs the mapper while it is locked. Failing instead of spinning avoids
+/// deadlocking in these cases. Interrupts are disabled while `f` runs.
+pub fn with_global<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BuddyFrameAllocator) -> R,
+) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let mut global = GLOBAL_MAPPER.try_lock()?;
+        let (mapper, frame_allocator) = global.as_mut()?;
+        Some(f(mapper, frame_allocator))
+    })
+}
+
+/// Returns the virtual address at which the complete physical memory is mapped, or `None` before `init` was called.
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    match PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) {
+        0 => None,
+        offset => Some(VirtAddr::new(offset)),
+    }
+}
+
+/// Returns whether the given address is mapped in the active page table.
+///
+/// Unlike the `Translate` trait, this does not need the global mapper, so it also works while the mapper is locked, for
+/// example in a panic. Returns `false` before `init` was called.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    use x86_64::registers::control::Cr3;
+
+    let offset = match physical_memory_offset() {
+        Some(offset) => offset.as_u64(),
+        None => return false,
+    };
+    let mut table_address = Cr3::read().0.start_address();
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    for (level, &index) in indexes.iter().enumerate() {
+        let table = unsafe { &*VirtAddr::new(offset + table_address.as_u64()).as_ptr::<PageTable>() };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return false;
+        }
+        // level 3 and level 2 entries can map 1 GiB and 2 MiB pages
+        if (level == 1 || level == 2) && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return true;
+        }
+        table_address = entry.addr();
+    }
+    true
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// The active_level_4_table function should only be called from the init function from now on because it can easily lead to aliased mutable references when called multiple times, which can cause undefined behavior. For this reason, we make the function private by removing the pub specifier.
@@ -0,0 +1,132 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// The size of a physical frame in bytes.
+const FRAME_SIZE: u64 = 4096;
+/// The number of frames tracked by a single word of the bitmap.
+const BITS_PER_WORD: usize = 64;
+
+/// A FrameAllocator that tracks every usable 4 KiB frame in a bitmap.
+///
+/// In contrast to the `BootInfoFrameAllocator`, frames can be given back through the
+/// `FrameDeallocator` trait, and allocation does not rescan the memory map on every call.
+/// A set bit means that the corresponding frame is free, a cleared bit means that it is
+/// either in use or not usable at all.
+pub struct BitmapFrameAllocator {
+    // one bit per 4 KiB frame, starting at physical address 0
+    // The bitmap itself lives in the first usable region that is large enough to hold it.
+    bitmap: &'static mut [u64],
+    // index of the first word that might still contain a free frame
+    // All words before `next` are guaranteed to be zero, so allocation never has to look at them.
+    next: usize,
+    // number of frames that are currently free
+    free_frames: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a BitmapFrameAllocator from the passed memory map.
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid, that all frames marked as `USABLE` in it are really unused,
+    /// and that the complete physical memory is mapped at `physical_memory_offset`.
+    /// Also, this function must be only called once, since the bitmap is stored in
+    /// (and thus aliases) usable physical memory.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // 1. the bitmap needs one bit for every frame below the end of the highest usable region
+        let max_addr = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        let word_count = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_bytes = (word_count * 8) as u64;
+        let bitmap_frames = bitmap_bytes.div_ceil(FRAME_SIZE);
+
+        // 2. place the bitmap at the start of the first usable region that is large enough
+        //    The region is accessed through the complete physical memory mapping set up by the bootloader.
+        let bitmap_start = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_frames * FRAME_SIZE)
+            .map(|r| r.range.start_addr())
+            .expect("no usable region is large enough for the frame bitmap");
+        let bitmap_end = bitmap_start + bitmap_frames * FRAME_SIZE;
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, word_count);
+
+        // 3. start with every frame marked as used and then free the usable ones
+        //    The frames occupied by the bitmap itself stay marked as used.
+        bitmap.fill(0);
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            next: 0,
+            free_frames: 0,
+        };
+        let addr_ranges = usable_regions().map(|r| r.range.start_addr()..r.range.end_addr());
+        for addr in addr_ranges.flat_map(|r| r.step_by(FRAME_SIZE as usize)) {
+            if addr >= bitmap_start && addr < bitmap_end {
+                continue;
+            }
+            allocator.set_free(Self::frame_index(addr));
+        }
+        allocator
+    }
+
+    /// Returns the number of frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Converts a physical address into an index into the bitmap.
+    fn frame_index(addr: u64) -> usize {
+        (addr / FRAME_SIZE) as usize
+    }
+
+    /// Marks the frame with the given index as free.
+    ///
+    /// Panics if the frame is already free, since that indicates a double free.
+    fn set_free(&mut self, index: usize) {
+        let (word, bit) = (index / BITS_PER_WORD, index % BITS_PER_WORD);
+        assert!(
+            self.bitmap[word] & (1 << bit) == 0,
+            "frame {:#x} freed twice",
+            index as u64 * FRAME_SIZE
+        );
+        self.bitmap[word] |= 1 << bit;
+        self.free_frames += 1;
+        // keep the invariant that all words before `next` are zero
+        self.next = self.next.min(word);
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // 1. skip all words that have no free frame left
+        //    Every skipped word stays skipped until a frame in it is freed again, so the scan is amortized O(1).
+        while self.next < self.bitmap.len() && self.bitmap[self.next] == 0 {
+            self.next += 1;
+        }
+        let word = self.bitmap.get_mut(self.next)?;
+        // 2. take the lowest free frame of the word and mark it as used
+        let bit = word.trailing_zeros() as usize;
+        *word &= !(1 << bit);
+        self.free_frames -= 1;
+
+        let addr = (self.next * BITS_PER_WORD + bit) as u64 * FRAME_SIZE;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.set_free(Self::frame_index(frame.start_address().as_u64()));
+    }
+}
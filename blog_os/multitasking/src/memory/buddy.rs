@@ -0,0 +1,283 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// The size of a physical frame in bytes.
+const FRAME_SIZE: u64 = 4096;
+
+/// The largest supported order.
+///
+/// A block of order `n` consists of `2^n` physically contiguous frames, so the largest
+/// block is 4 MiB. Order 9 (512 frames) is exactly one 2 MiB huge page.
+pub const MAX_ORDER: usize = 10;
+
+/// The order of a block that backs a 2 MiB huge page.
+const HUGE_PAGE_ORDER: usize = 9;
+
+/// Returns the size in bytes of a block of the given order.
+const fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+/// The number of blocks tracked by a single word of a free bitmap.
+const BITS_PER_WORD: u64 = 64;
+
+struct FreeBlock {
+    // Like the ListNode of the fixed-size block allocator, the node is stored in the free block itself, so we don't need a size field.
+    // The order is implied by the free list that the block is part of. The list is doubly linked and the neighbours are stored as
+    // physical addresses, so that a block can be removed from the middle of its list without walking it.
+    next: Option<u64>,
+    prev: Option<u64>,
+}
+
+/// A buddy-system physical frame allocator.
+///
+/// Every usable region of the memory map is split into naturally aligned blocks of
+/// `2^order` frames. Allocations split larger blocks in halves ("buddies") until the
+/// requested order is reached, and deallocations merge a block with its buddy again
+/// whenever the buddy is free too.
+pub struct BuddyFrameAllocator {
+    // physical address of the first free block of every order
+    free_lists: [Option<u64>; MAX_ORDER + 1],
+    // one bit per naturally aligned block of every order, set if the block is in the free list of that order
+    // The bitmaps of all orders are stored one after another in the first usable region that is large enough to hold them.
+    free_bitmap: &'static mut [u64],
+    // index of the first word of the bitmap of every order
+    bitmap_offsets: [usize; MAX_ORDER + 1],
+    // the free blocks are accessed through the complete physical memory mapping
+    physical_memory_offset: VirtAddr,
+    // number of 4 KiB frames that are currently free
+    free_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Create a BuddyFrameAllocator from the passed memory map.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// memory map is valid, that all frames marked as `USABLE` in it are really unused,
+    /// and that the complete physical memory is mapped at `physical_memory_offset`.
+    /// Also, this function must be only called once, since the free lists and the
+    /// bitmap are stored in (and thus alias) usable physical memory.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // 1. every order needs one bit for every block below the end of the highest usable region
+        let max_addr = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let mut bitmap_offsets = [0; MAX_ORDER + 1];
+        let mut word_count = 0;
+        for (order, offset) in bitmap_offsets.iter_mut().enumerate() {
+            *offset = word_count;
+            word_count += max_addr.div_ceil(block_size(order)).div_ceil(BITS_PER_WORD) as usize;
+        }
+        let bitmap_frames = (word_count as u64 * 8).div_ceil(FRAME_SIZE);
+
+        // 2. place the bitmap at the start of the first usable region that is large enough, like the BitmapFrameAllocator
+        let bitmap_start = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_frames * FRAME_SIZE)
+            .map(|r| r.range.start_addr())
+            .expect("no usable region is large enough for the buddy bitmap");
+        let bitmap_end = bitmap_start + bitmap_frames * FRAME_SIZE;
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let free_bitmap = slice::from_raw_parts_mut(bitmap_ptr, word_count);
+        free_bitmap.fill(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            free_lists: [None; MAX_ORDER + 1],
+            free_bitmap,
+            bitmap_offsets,
+            physical_memory_offset,
+            free_frames: 0,
+        };
+        // 3. free the usable regions, except for the frames of the bitmap
+        for region in usable_regions() {
+            let (start, end) = (region.range.start_addr(), region.range.end_addr());
+            allocator.free_range(start, end.min(bitmap_start));
+            allocator.free_range(start.max(bitmap_end), end);
+        }
+        allocator
+    }
+
+    /// Returns the number of 4 KiB frames that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Allocates `2^order` physically contiguous frames.
+    ///
+    /// Returns the first frame of the block. The block is aligned to its own size, so an
+    /// order 9 block can directly back a 2 MiB huge page.
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        // 1. find the smallest order with a free block that is at least as large as requested
+        let found_order = (order..=MAX_ORDER).find(|&o| self.free_lists[o].is_some())?;
+        let addr = self.pop_block(found_order)?;
+        // 2. split the block in halves until it has the requested order
+        //    The upper half of every split is put back into the free list of the next lower order.
+        for split_order in (order..found_order).rev() {
+            self.push_block(addr + block_size(split_order), split_order);
+        }
+        self.free_frames -= 1 << order;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// Frees a block of `2^order` frames that was allocated through `allocate_contiguous`.
+    ///
+    /// Panics if any part of the block is already free, since that indicates a double free.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the block is
+    /// unused and was allocated with the same `order`.
+    pub unsafe fn deallocate_contiguous(&mut self, frame: PhysFrame, order: usize) {
+        assert!(order <= MAX_ORDER, "invalid order {}", order);
+        let mut addr = frame.start_address().as_u64();
+        assert_eq!(addr % block_size(order), 0, "block is not aligned to its order");
+        assert!(!self.overlaps_free_block(addr, order), "block {:#x} of order {} freed twice", addr, order);
+        self.free_frames += 1 << order;
+
+        // merge the block with its buddy for as long as the buddy is free too
+        // The buddy of a block is the other half of the block of the next higher order, so its address only differs in a single bit.
+        let mut order = order;
+        while order < MAX_ORDER {
+            let buddy = addr ^ block_size(order);
+            if !self.remove_block(buddy, order) {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push_block(addr, order);
+    }
+
+    /// Frees the physical address range `start..end` as the largest naturally aligned blocks that fit.
+    unsafe fn free_range(&mut self, start: u64, end: u64) {
+        let mut addr = start;
+        while addr < end {
+            let mut order = MAX_ORDER;
+            while !addr.is_multiple_of(block_size(order)) || addr + block_size(order) > end {
+                order -= 1;
+            }
+            self.deallocate_contiguous(PhysFrame::containing_address(PhysAddr::new(addr)), order);
+            addr += block_size(order);
+        }
+    }
+
+    /// Returns the word of the free bitmap and the bit in it that belong to the block at `addr`.
+    ///
+    /// Returns `None` for blocks above the highest usable region, which are never free.
+    fn bitmap_bit(&self, addr: u64, order: usize) -> Option<(usize, u64)> {
+        let index = addr / block_size(order);
+        let word = self.bitmap_offsets[order] + (index / BITS_PER_WORD) as usize;
+        let next_offset = self.bitmap_offsets.get(order + 1).copied().unwrap_or(self.free_bitmap.len());
+        (word < next_offset).then(|| (word, 1 << (index % BITS_PER_WORD)))
+    }
+
+    /// Returns whether the block at the given physical address is in the free list of `order`.
+    fn is_free(&self, addr: u64, order: usize) -> bool {
+        self.bitmap_bit(addr, order)
+            .is_some_and(|(word, bit)| self.free_bitmap[word] & bit != 0)
+    }
+
+    /// Returns whether any part of the block at `addr` is free.
+    ///
+    /// A freed block may have been merged with its buddy, so the larger blocks that contain it are checked too, and
+    /// so are the smaller blocks inside of it, which may have been freed separately.
+    fn overlaps_free_block(&self, addr: u64, order: usize) -> bool {
+        let larger = (order..=MAX_ORDER).any(|o| self.is_free(addr & !(block_size(o) - 1), o));
+        let smaller = (0..order).any(|o| {
+            (addr..addr + block_size(order))
+                .step_by(block_size(o) as usize)
+                .any(|sub_block| self.is_free(sub_block, o))
+        });
+        larger || smaller
+    }
+
+    /// Returns a pointer to the list node of the free block at the given physical address.
+    fn node(&self, addr: u64) -> *mut FreeBlock {
+        (self.physical_memory_offset + addr).as_mut_ptr()
+    }
+
+    /// Adds the block at the given physical address to the front of the free list of `order`.
+    fn push_block(&mut self, addr: u64, order: usize) {
+        let (word, bit) = self.bitmap_bit(addr, order).expect("block is above the highest usable region");
+        let head = self.free_lists[order];
+        unsafe {
+            self.node(addr).write(FreeBlock { next: head, prev: None });
+            if let Some(head) = head {
+                (*self.node(head)).prev = Some(addr);
+            }
+        }
+        self.free_lists[order] = Some(addr);
+        self.free_bitmap[word] |= bit;
+    }
+
+    /// Removes the first block from the free list of `order` and returns its physical address.
+    fn pop_block(&mut self, order: usize) -> Option<u64> {
+        let addr = self.free_lists[order]?;
+        self.remove_block(addr, order);
+        Some(addr)
+    }
+
+    /// Removes the block at the given physical address from the free list of `order`.
+    ///
+    /// Returns `false` if the block is not in the list, i.e. if it is not free. The bitmap
+    /// answers that without walking the list, and the list is doubly linked, so this is O(1).
+    fn remove_block(&mut self, addr: u64, order: usize) -> bool {
+        let (word, bit) = match self.bitmap_bit(addr, order) {
+            Some((word, bit)) if self.free_bitmap[word] & bit != 0 => (word, bit),
+            _ => return false,
+        };
+        unsafe {
+            let FreeBlock { next, prev } = self.node(addr).read();
+            match prev {
+                Some(prev) => (*self.node(prev)).next = next,
+                None => self.free_lists[order] = next,
+            }
+            if let Some(next) = next {
+                (*self.node(next)).prev = prev;
+            }
+        }
+        self.free_bitmap[word] &= !bit;
+        true
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_contiguous(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_contiguous(frame, 0)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate_contiguous(HUGE_PAGE_ORDER)?;
+        Some(PhysFrame::containing_address(frame.start_address()))
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        self.deallocate_contiguous(frame, HUGE_PAGE_ORDER)
+    }
+}
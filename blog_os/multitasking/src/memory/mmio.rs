@@ -0,0 +1,124 @@
+// Memory-mapped I/O
+//
+// Device registers must not be cached: a read has to reach the device every time, and a write must not be delayed or
+// combined with other writes. `map_mmio` therefore maps the physical range into the vmalloc area with caching disabled,
+// instead of accessing it through the (cached) mapping of the complete physical memory. The registers are accessed with
+// volatile reads and writes, so that the compiler does not remove or reorder them either.
+
+use super::region::Backing;
+use super::vmalloc::{self, VmallocError};
+use super::with_global;
+use core::mem;
+use core::ptr;
+use x86_64::structures::paging::{Mapper, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The flags of MMIO pages.
+pub const MMIO_FLAGS: PageTableFlags = PageTableFlags::from_bits_truncate(
+    PageTableFlags::WRITABLE.bits()
+        | PageTableFlags::NO_CACHE.bits()
+        | PageTableFlags::WRITE_THROUGH.bits()
+        | PageTableFlags::NO_EXECUTE.bits(),
+);
+
+/// A type that can be read from and written to device registers.
+pub trait MmioValue: Copy {}
+
+impl MmioValue for u8 {}
+impl MmioValue for u16 {}
+impl MmioValue for u32 {}
+impl MmioValue for u64 {}
+
+/// A physical address range that is mapped uncached, like the registers of a device. It is unmapped when dropped.
+#[derive(Debug)]
+pub struct MmioRegion {
+    phys_addr: PhysAddr,
+    virt_addr: VirtAddr,
+    size: usize,
+}
+
+impl MmioRegion {
+    /// Returns the physical address of the first byte.
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys_addr
+    }
+
+    /// Returns the virtual address that `phys_addr` is mapped to.
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt_addr
+    }
+
+    /// Returns the size in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reads the value at `offset` bytes from the start.
+    ///
+    /// Panics if the value is not inside of the region or if `offset` is not aligned for `T`.
+    pub fn read<T: MmioValue>(&self, offset: usize) -> T {
+        unsafe { ptr::read_volatile(self.pointer::<T>(offset)) }
+    }
+
+    /// Writes the value at `offset` bytes from the start.
+    ///
+    /// Panics if the value is not inside of the region or if `offset` is not aligned for `T`.
+    pub fn write<T: MmioValue>(&self, offset: usize, value: T) {
+        unsafe { ptr::write_volatile(self.pointer::<T>(offset), value) }
+    }
+
+    fn pointer<T: MmioValue>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset.checked_add(mem::size_of::<T>()).is_some_and(|end| end <= self.size),
+            "MMIO access at offset {:#x} is outside of the region ({:#x} bytes)",
+            offset,
+            self.size
+        );
+        let addr = self.virt_addr + offset;
+        assert!(addr.is_aligned(mem::align_of::<T>() as u64), "unaligned MMIO access at offset {:#x}", offset);
+        addr.as_mut_ptr()
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        // the frames belong to the device, so only the pages are unmapped
+        vmalloc::vfree(self.virt_addr.align_down(PAGE_SIZE));
+    }
+}
+
+/// Maps the `size` bytes at `phys_addr` uncached and non-executable.
+///
+/// The address does not need to be page-aligned. Mapping the same range more than once is fine.
+///
+/// This function is unsafe because the caller must guarantee that the range is device memory: it is mapped writable,
+/// so if it aliased RAM, the safe `MmioRegion::write` could overwrite the heap, page tables or any other kernel data.
+pub unsafe fn map_mmio(phys_addr: PhysAddr, size: usize) -> Result<MmioRegion, VmallocError> {
+    let first_frame: PhysFrame = PhysFrame::containing_address(phys_addr);
+    let page_offset = phys_addr - first_frame.start_address();
+    let region = vmalloc::reserve(page_offset + size as u64, MMIO_FLAGS, Backing::Physical(first_frame.start_address()))?;
+
+    // map everything now: a driver may access its registers while the mapper is locked, e.g. in an interrupt handler
+    let mapped = with_global(|mapper, frame_allocator| {
+        for (i, page) in region.pages().enumerate() {
+            let frame = first_frame + i as u64;
+            let flags = MMIO_FLAGS | PageTableFlags::PRESENT;
+            unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        }
+        Ok(())
+    });
+
+    let mmio = MmioRegion {
+        phys_addr,
+        virt_addr: region.start + page_offset,
+        size,
+    };
+    match mapped {
+        Some(Ok(())) => Ok(mmio),
+        // dropping the region unmaps the pages that were already mapped
+        Some(Err(err)) => Err(VmallocError::Map(err)),
+        None => Err(VmallocError::MapperUnavailable),
+    }
+}
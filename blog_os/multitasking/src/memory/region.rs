@@ -0,0 +1,249 @@
+// Virtual memory regions
+//
+// A region is a page-aligned range of the virtual address space together with the flags of its pages and what backs
+// them. The page fault handler asks the registry about every fault: pages of demand-paged regions are mapped on their
+// first access, all other faults are real errors.
+
+use super::{physical_memory_offset, with_global};
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+/// What the pages of a region are mapped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Zeroed frames, which are allocated and mapped on the first access to a page.
+    Anonymous,
+    /// The physical memory starting at the given address (e.g. a device), mapped on the first access to a page.
+    Physical(PhysAddr),
+    /// The owner of the region maps the pages itself (e.g. the heap). A fault in the region is an error.
+    Mapped,
+}
+
+/// A range of the virtual address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: VirtAddr,
+    /// The length in bytes, a multiple of the page size.
+    pub len: u64,
+    /// The flags of the mapped pages. `PRESENT` is added when a page is mapped.
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl Region {
+    /// Returns the address after the last byte of the region.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.len
+    }
+
+    /// Returns whether the region contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr < self.end()
+    }
+
+    /// Returns the pages of the region.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(self.start);
+        Page::range(start, start + self.len / PAGE_SIZE)
+    }
+}
+
+/// An error while registering a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The start, the length or the physical address is not page-aligned, or the length is zero.
+    Unaligned,
+    /// The region overlaps with the given registered region.
+    Overlap(Region),
+    /// The end of the region is outside of the address space.
+    OutOfRange,
+    /// There is no free range of the requested length in the area passed to `allocate`.
+    NoSpace,
+}
+
+// registered regions, by their start address
+static REGIONS: spin::Mutex<BTreeMap<u64, Region>> = spin::Mutex::new(BTreeMap::new());
+
+/// Adds a region to the registry.
+///
+/// Demand-paged regions are not mapped yet; their pages are mapped by the page fault handler on the first access.
+pub fn register(region: Region) -> Result<(), RegionError> {
+    check_alignment(region.start, region.len, region.backing)?;
+    region
+        .start
+        .as_u64()
+        .checked_add(region.len)
+        .and_then(|end| VirtAddr::try_new(end - 1).ok())
+        .ok_or(RegionError::OutOfRange)?;
+
+    // the page fault handler locks the registry, so it must not interrupt us while we hold the lock
+    interrupts::without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        let start = region.start.as_u64();
+        let before = regions.range(..=start).next_back().map(|(_, r)| *r);
+        let after = regions.range(start..).next().map(|(_, r)| *r);
+        if let Some(other) = before.filter(|other| other.end() > region.start) {
+            return Err(RegionError::Overlap(other));
+        }
+        if let Some(other) = after.filter(|other| other.start < region.end()) {
+            return Err(RegionError::Overlap(other));
+        }
+        regions.insert(start, region);
+        Ok(())
+    })
+}
+
+/// Registers a region of `len` bytes at the lowest free address in `area` and returns it.
+///
+/// The region is not placed directly next to another registered region: an unmapped guard page separates them, so that
+/// running over the end of the region faults instead of silently accessing the neighbouring one.
+pub fn allocate(area: Range<VirtAddr>, len: u64, flags: PageTableFlags, backing: Backing) -> Result<Region, RegionError> {
+    check_alignment(area.start, len, backing)?;
+    let area_end = area.end.as_u64();
+
+    interrupts::without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        // the region below the area needs a guard page as well, if it ends at the start of the area or inside of it
+        let mut start = area.start.as_u64();
+        if let Some((_, before)) = regions.range(..start).next_back() {
+            start = start.max(before.end().as_u64() + PAGE_SIZE);
+        }
+        for (_, other) in regions.range(area.start.as_u64()..area_end) {
+            let end_with_guard = start
+                .checked_add(len)
+                .and_then(|end| end.checked_add(PAGE_SIZE))
+                .ok_or(RegionError::NoSpace)?;
+            if end_with_guard <= other.start.as_u64() {
+                break;
+            }
+            start = start.max(other.end().as_u64() + PAGE_SIZE);
+        }
+        match start.checked_add(len) {
+            Some(end) if end <= area_end => {}
+            _ => return Err(RegionError::NoSpace),
+        }
+
+        let region = Region {
+            start: VirtAddr::new(start),
+            len,
+            flags,
+            backing,
+        };
+        regions.insert(start, region);
+        Ok(region)
+    })
+}
+
+/// Removes the region that starts at `start` from the registry and returns it.
+///
+/// The pages that were mapped on demand are unmapped, and the frames of anonymous regions are freed. The pages of
+/// `Mapped` regions are left to their owner.
+pub fn unregister(start: VirtAddr) -> Option<Region> {
+    let region = interrupts::without_interrupts(|| REGIONS.lock().remove(&start.as_u64()))?;
+    if region.backing != Backing::Mapped {
+        with_global(|mapper, frame_allocator| {
+            for page in region.pages() {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                    if region.backing == Backing::Anonymous {
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                    }
+                }
+            }
+        });
+    }
+    Some(region)
+}
+
+/// Returns the region that contains the given address.
+pub fn find(addr: VirtAddr) -> Option<Region> {
+    interrupts::without_interrupts(|| find_in(&REGIONS.lock(), addr))
+}
+
+/// Like `find`, but returns `None` instead of waiting if the registry is locked, e.g. by the code that an exception
+/// interrupted.
+pub fn try_find(addr: VirtAddr) -> Option<Region> {
+    find_in(&*REGIONS.try_lock()?, addr)
+}
+
+fn find_in(regions: &BTreeMap<u64, Region>, addr: VirtAddr) -> Option<Region> {
+    let (_, region) = regions.range(..=addr.as_u64()).next_back()?;
+    Some(*region).filter(|region| region.contains(addr))
+}
+
+fn check_alignment(start: VirtAddr, len: u64, backing: Backing) -> Result<(), RegionError> {
+    let physical_aligned = match backing {
+        Backing::Physical(addr) => addr.is_aligned(PAGE_SIZE),
+        _ => true,
+    };
+    if len == 0 || !len.is_multiple_of(PAGE_SIZE) || !start.is_aligned(PAGE_SIZE) || !physical_aligned {
+        return Err(RegionError::Unaligned);
+    }
+    Ok(())
+}
+
+/// Called by the page fault handler. Maps the page at `addr` if it belongs to a demand-paged region and the access is
+/// allowed by the flags of the region.
+///
+/// Returns `false` for all other faults. Like `memory::with_global`, it also fails if the registry or the mapper are
+/// locked by the interrupted code, instead of deadlocking.
+pub(crate) fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let region = match try_find(addr) {
+        Some(region) => region,
+        None => return false,
+    };
+    // a fault on a present page is a protection violation, which mapping cannot fix
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        || (error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) && !region.flags.contains(PageTableFlags::WRITABLE))
+        || (error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) && region.flags.contains(PageTableFlags::NO_EXECUTE))
+    {
+        return false;
+    }
+
+    let page: Page = Page::containing_address(addr);
+    let flags = region.flags | PageTableFlags::PRESENT;
+    with_global(|mapper, frame_allocator| {
+        let frame = match region.backing {
+            Backing::Anonymous => {
+                let frame = match frame_allocator.allocate_frame() {
+                    Some(frame) => frame,
+                    None => return false,
+                };
+                zero_frame(frame);
+                frame
+            }
+            Backing::Physical(start) => {
+                PhysFrame::containing_address(start + (page.start_address() - region.start))
+            }
+            Backing::Mapped => return false,
+        };
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => {
+                flush.flush();
+                true
+            }
+            Err(_) => {
+                if region.backing == Backing::Anonymous {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+                false
+            }
+        }
+    })
+    .unwrap_or(false)
+}
+
+/// Fills a frame with zeros through the mapping of the complete physical memory.
+fn zero_frame(frame: PhysFrame) {
+    let offset = physical_memory_offset().expect("memory::init was not called");
+    let virt = offset + frame.start_address().as_u64();
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize) };
+}
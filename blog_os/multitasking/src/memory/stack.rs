@@ -0,0 +1,105 @@
+// Kernel stacks
+//
+// Stacks are allocated from the vmalloc area. Regions of the vmalloc area are separated by an unmapped guard page, so
+// below every stack is a page that is never mapped: a stack overflow runs into the guard page and faults, instead of
+// silently overwriting the memory below the stack.
+
+use super::region::{self, Backing};
+use super::vmalloc::{self, VmallocError};
+use super::with_global;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = 4096;
+
+const STACK_FLAGS: PageTableFlags = PageTableFlags::from_bits_truncate(
+    PageTableFlags::WRITABLE.bits() | PageTableFlags::NO_EXECUTE.bits(),
+);
+
+/// A stack with a guard page below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stack {
+    guard_page: Page,
+    pages: u64,
+}
+
+impl Stack {
+    /// The address above the highest byte of the stack, which is the initial stack pointer.
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.pages * PAGE_SIZE
+    }
+
+    /// The lowest address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        (self.guard_page + 1).start_address()
+    }
+
+    /// The unmapped page below the stack.
+    pub fn guard_page(&self) -> Page {
+        self.guard_page
+    }
+}
+
+/// Maps a stack of `pages` pages with a guard page below it.
+pub fn allocate_stack(pages: u64) -> Result<Stack, VmallocError> {
+    // the pages are mapped here, so that a stack never needs the page fault handler
+    let region = vmalloc::reserve(pages * PAGE_SIZE, STACK_FLAGS, Backing::Mapped)?;
+    let stack = Stack {
+        guard_page: Page::containing_address(region.start) - 1,
+        pages,
+    };
+
+    let mapped = with_global(|mapper, frame_allocator| {
+        for page in region.pages() {
+            if let Err(err) = map_page(page, mapper, frame_allocator) {
+                // give back the pages that were already mapped
+                for mapped in Page::range(stack.guard_page + 1, page) {
+                    if let Ok((frame, flush)) = mapper.unmap(mapped) {
+                        flush.flush();
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                    }
+                }
+                return Err(VmallocError::Map(err));
+            }
+        }
+        Ok(stack)
+    })
+    .unwrap_or(Err(VmallocError::MapperUnavailable));
+    if mapped.is_err() {
+        vmalloc::vfree(region.start);
+    }
+    mapped
+}
+
+/// Returns whether `addr` is in the guard page of a stack.
+///
+/// Stacks are the only `Mapped` regions of the vmalloc area, and the page below a stack is never mapped. It does not
+/// wait for the region registry, so it can be called from the double fault handler, but it returns `false` if the
+/// registry is locked.
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    let above = Page::<Size4KiB>::containing_address(addr) + 1;
+    match region::try_find(above.start_address()) {
+        Some(region) => {
+            region.start == above.start_address()
+                && region.backing == Backing::Mapped
+                && vmalloc::is_vmalloc_address(region.start)
+                && !super::is_mapped(addr)
+        }
+        None => false,
+    }
+}
+
+fn map_page(
+    page: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = STACK_FLAGS | PageTableFlags::PRESENT;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    Ok(())
+}
@@ -0,0 +1,95 @@
+// Kernel virtual memory allocator
+//
+// Hands out ranges of the vmalloc area, a part of the kernel address space that is reserved for this, instead of
+// hand-picked addresses like `HEAP_START`. Kernel stacks (see `stack`) are allocated from it as well. Every range is a region of the `region` registry, so the free ranges are
+// the gaps between the registered regions, and neighbouring ranges are separated by an unmapped guard page.
+//
+// `vmalloc` maps the pages of a range to frames from the global frame allocator. The frames do not need to be
+// contiguous, only the virtual range is.
+
+use super::region::{self, Backing, Region, RegionError};
+use super::with_global;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// The virtual address range that `vmalloc` allocates from.
+pub const VMALLOC_START: u64 = 0x_6000_0000_0000;
+pub const VMALLOC_SIZE: u64 = 64 * 1024 * 1024 * 1024; // 64 GiB
+
+const PAGE_SIZE: u64 = 4096;
+
+/// An error while allocating virtual memory.
+#[derive(Debug)]
+pub enum VmallocError {
+    /// The requested size is zero, or there is no free range of that size.
+    Region(RegionError),
+    /// `memory::init_global` was not called yet, or the mapper is in use.
+    MapperUnavailable,
+    Map(MapToError<Size4KiB>),
+}
+
+/// Allocates `size` bytes of virtual memory, rounded up to whole pages, and maps them with the given flags.
+///
+/// The memory is not zeroed. Pass the start of the returned region to `vfree` to unmap it and free its frames.
+pub fn vmalloc(size: u64, flags: PageTableFlags) -> Result<Region, VmallocError> {
+    let region = reserve(size, flags, Backing::Anonymous)?;
+    let mapped = with_global(|mapper, frame_allocator| {
+        for page in region.pages() {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            match unsafe { mapper.map_to(page, frame, flags | PageTableFlags::PRESENT, frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(err) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    });
+
+    match mapped {
+        Some(Ok(())) => Ok(region),
+        failed => {
+            // unmaps the pages that were already mapped and frees their frames
+            region::unregister(region.start);
+            Err(match failed {
+                Some(Err(err)) => VmallocError::Map(err),
+                _ => VmallocError::MapperUnavailable,
+            })
+        }
+    }
+}
+
+/// Reserves `size` bytes of the vmalloc area, rounded up to whole pages, without mapping anything.
+///
+/// Pages of `Anonymous` and `Physical` regions are mapped on demand by the page fault handler; the pages of `Mapped`
+/// regions must be mapped by the caller. Pass the start of the returned region to `vfree` to release it.
+pub fn reserve(size: u64, flags: PageTableFlags, backing: Backing) -> Result<Region, VmallocError> {
+    if size > VMALLOC_SIZE {
+        return Err(VmallocError::Region(RegionError::NoSpace));
+    }
+    let len = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let area = VirtAddr::new(VMALLOC_START)..VirtAddr::new(VMALLOC_START + VMALLOC_SIZE);
+    region::allocate(area, len, flags, backing).map_err(VmallocError::Region)
+}
+
+/// Releases the range that starts at `start` and returns its region, or `None` if no range starts there.
+///
+/// Like `region::unregister`, it unmaps the pages and frees the frames that `vmalloc` or the page fault handler
+/// allocated.
+pub fn vfree(start: VirtAddr) -> Option<Region> {
+    // regions outside of the area belong to someone else
+    if !is_vmalloc_address(start) {
+        return None;
+    }
+    region::unregister(start)
+}
+
+/// Returns whether `addr` is in the vmalloc area.
+pub fn is_vmalloc_address(addr: VirtAddr) -> bool {
+    (VMALLOC_START..VMALLOC_START + VMALLOC_SIZE).contains(&addr.as_u64())
+}
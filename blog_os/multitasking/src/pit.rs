@@ -0,0 +1,60 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use x86_64::instructions::{interrupts, port::Port};
+
+/// Frequency of the oscillator that drives the 8253/8254 programmable interval timer (PIT), in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+// Channel 0 is connected to IRQ 0, i.e. `InterruptIndex::Timer`.
+const CHANNEL_0_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+// channel 0, access mode lobyte/hibyte, mode 2 (rate generator), binary counting
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+// The BIOS programs the maximum reload value (written as 0), which gives about 18.2 interrupts per second.
+static DIVISOR: AtomicU32 = AtomicU32::new(65536);
+// whether channel 0 was switched to the rate generator mode
+static RATE_GENERATOR: AtomicBool = AtomicBool::new(false);
+
+/// Programs channel 0 to raise the timer interrupt `frequency` times per second.
+///
+/// The PIT can only divide its base frequency by an integer between 1 and 65536, so the frequency is rounded to the
+/// nearest possible one, which is returned.
+///
+/// Only the first call writes the command register, which restarts the counter. After that, only the reload value is
+/// written, which the PIT loads when the period in progress ends: the next interrupt still comes after the old period.
+pub fn set_frequency(frequency: u32) -> u32 {
+    let divisor = ((BASE_FREQUENCY + frequency / 2) / frequency.max(1)).clamp(1, 65536);
+    interrupts::without_interrupts(|| {
+        let mut command: Port<u8> = Port::new(COMMAND_PORT);
+        let mut data = Port::new(CHANNEL_0_PORT);
+        unsafe {
+            if !RATE_GENERATOR.swap(true, Ordering::Relaxed) {
+                command.write(CHANNEL_0_RATE_GENERATOR);
+            }
+            // a reload value of 0 stands for 65536
+            data.write(divisor as u8);
+            data.write((divisor >> 8) as u8);
+        }
+        DIVISOR.store(divisor, Ordering::Relaxed);
+    });
+    frequency_for(divisor)
+}
+
+/// Returns the current interrupt frequency of channel 0, rounded to Hz.
+pub fn frequency() -> u32 {
+    frequency_for(DIVISOR.load(Ordering::Relaxed))
+}
+
+/// Returns the current reload value of channel 0, i.e. the number of `BASE_FREQUENCY` cycles between two timer interrupts.
+pub fn divisor() -> u32 {
+    DIVISOR.load(Ordering::Relaxed)
+}
+
+/// Returns the time between two timer interrupts in nanoseconds (rounded down).
+pub fn period_nanos() -> u64 {
+    u64::from(divisor()) * 1_000_000_000 / u64::from(BASE_FREQUENCY)
+}
+
+fn frequency_for(divisor: u32) -> u32 {
+    (BASE_FREQUENCY + divisor / 2) / divisor
+}
@@ -0,0 +1,160 @@
+// Shutting down and rebooting the machine
+//
+// `shutdown` puts the system into the ACPI sleep state S5 (soft off). The value that selects S5 is not in a fixed table,
+// but in the `_S5_` object of the AML code in the DSDT. Without ACPI, the ports of the QEMU and Bochs power management
+// devices are tried. `reboot` pulses the reset line through the keyboard controller and triple faults if that fails.
+
+use crate::acpi::{self, Fadt};
+use x86_64::instructions::{interrupts, port::Port, tables::lidt};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+// PM1 control register bits
+const SCI_EN: u16 = 1 << 0;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+const SLP_EN: u16 = 1 << 13;
+
+/// How often a status register is read while waiting for the hardware, before giving up.
+const POLL_RETRIES: usize = 1_000_000;
+
+// (port, value) pairs that power off emulators: QEMU with the ICH9 and PIIX4 chipsets, and Bochs or old QEMU versions
+const EMULATOR_SHUTDOWN: [(u16, u16); 2] = [(0x604, 0x2000), (0xb004, 0x2000)];
+
+// 8042 keyboard controller
+const KBC_STATUS: u16 = 0x64;
+const KBC_COMMAND: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xfe;
+
+/// Turns the machine off.
+///
+/// Needs the tables of `acpi::init`; without them, only the emulator ports are tried. If nothing works, the CPU halts.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    if let Some(fadt) = acpi::tables().and_then(|tables| tables.fadt.as_ref()) {
+        if let Some((slp_typ_a, slp_typ_b)) = s5_sleep_type() {
+            unsafe { enter_sleep_state(fadt, slp_typ_a, slp_typ_b) };
+        }
+    }
+    for &(port, value) in EMULATOR_SHUTDOWN.iter() {
+        unsafe { Port::new(port).write(value) };
+    }
+    crate::println!("shutdown failed, it is now safe to turn off the computer");
+    crate::hlt_loop();
+}
+
+/// Restarts the machine.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    unsafe {
+        // 1. let the keyboard controller pulse the reset line of the CPU
+        let mut status: Port<u8> = Port::new(KBC_STATUS);
+        let mut command: Port<u8> = Port::new(KBC_COMMAND);
+        for _ in 0..POLL_RETRIES {
+            if status.read() & KBC_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        command.write(KBC_PULSE_RESET);
+
+        // 2. triple fault: without an IDT, the breakpoint exception cannot be handled, and neither can the double fault
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+        interrupts::int3();
+    }
+    crate::hlt_loop();
+}
+
+/// Returns the SLP_TYPa and SLP_TYPb values of the S5 sleep state from the `_S5_` object of the DSDT.
+pub fn s5_sleep_type() -> Option<(u16, u16)> {
+    let tables = acpi::tables()?;
+    let dsdt = tables.acpi.table(tables.fadt.as_ref()?.dsdt).ok()?;
+    find_s5(dsdt)
+}
+
+/// Writes the sleep type to the PM1 control registers, after switching to ACPI mode if necessary.
+unsafe fn enter_sleep_state(fadt: &Fadt, slp_typ_a: u16, slp_typ_b: u16) {
+    let mut pm1a: Port<u16> = Port::new(fadt.pm1a_control_block as u16);
+    if pm1a.read() & SCI_EN == 0 && fadt.smi_command_port != 0 && fadt.acpi_enable != 0 {
+        Port::new(fadt.smi_command_port as u16).write(fadt.acpi_enable);
+        for _ in 0..POLL_RETRIES {
+            if pm1a.read() & SCI_EN != 0 {
+                break;
+            }
+        }
+    }
+
+    let value = pm1a.read() & !SLP_TYP_MASK;
+    pm1a.write(value | (slp_typ_a << SLP_TYP_SHIFT) | SLP_EN);
+    if fadt.pm1b_control_block != 0 {
+        let mut pm1b: Port<u16> = Port::new(fadt.pm1b_control_block as u16);
+        let value = pm1b.read() & !SLP_TYP_MASK;
+        pm1b.write(value | (slp_typ_b << SLP_TYP_SHIFT) | SLP_EN);
+    }
+}
+
+/// Finds `Name(_S5_, Package() { SLP_TYPa, SLP_TYPb, ... })` in the AML code of a table.
+///
+/// This is not an AML interpreter: it only understands the encoding that firmware uses for this object. Returns `None`
+/// if there is no such object or if it is truncated.
+pub fn find_s5(aml: &[u8]) -> Option<(u16, u16)> {
+    const NAME_OP: u8 = 0x08;
+    const ROOT_CHAR: u8 = b'\\';
+    const PACKAGE_OP: u8 = 0x12;
+
+    let start = aml.windows(4).enumerate().find_map(|(i, window)| {
+        // the name is either relative or starts at the root of the namespace
+        let name_op = match i {
+            0 => false,
+            1 => aml[0] == NAME_OP,
+            _ => aml[i - 1] == NAME_OP || (aml[i - 1] == ROOT_CHAR && aml[i - 2] == NAME_OP),
+        };
+        if window == b"_S5_" && name_op && aml.get(i + 4) == Some(&PACKAGE_OP) {
+            Some(i + 5)
+        } else {
+            None
+        }
+    })?;
+
+    // the top two bits of the first byte of the package length are the number of bytes that follow it
+    let pkg_length_bytes = 1 + usize::from(*aml.get(start)? >> 6);
+    // skip the package length and the number of elements
+    let mut index = start + pkg_length_bytes + 1;
+    let slp_typ_a = aml_integer(aml, &mut index)?;
+    let slp_typ_b = aml_integer(aml, &mut index)?;
+    Some((slp_typ_a as u16 & 0b111, slp_typ_b as u16 & 0b111))
+}
+
+/// Decodes the AML integer at `index` and advances `index` behind it.
+fn aml_integer(aml: &[u8], index: &mut usize) -> Option<u64> {
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const ONES_OP: u8 = 0xff;
+    const BYTE_PREFIX: u8 = 0x0a;
+    const WORD_PREFIX: u8 = 0x0b;
+    const DWORD_PREFIX: u8 = 0x0c;
+    const QWORD_PREFIX: u8 = 0x0e;
+
+    let len = match *aml.get(*index)? {
+        ZERO_OP | ONE_OP | ONES_OP => 0,
+        BYTE_PREFIX => 1,
+        WORD_PREFIX => 2,
+        DWORD_PREFIX => 4,
+        QWORD_PREFIX => 8,
+        _ => return None,
+    };
+    let value = match aml[*index] {
+        ZERO_OP => 0,
+        ONE_OP => 1,
+        ONES_OP => u64::MAX,
+        _ => {
+            let bytes = aml.get(*index + 1..*index + 1 + len)?;
+            bytes.iter().rev().fold(0, |value, &byte| value << 8 | u64::from(byte))
+        }
+    };
+    *index += 1 + len;
+    Some(value)
+}
@@ -0,0 +1,92 @@
+// Kernel symbol table
+//
+// The addresses of the kernel functions are only known after linking, so the table cannot be generated by the compiler.
+// Instead, the kernel reserves the `.ksyms` section, and `runner.sh` (the cargo runner) fills it with the output of `nm`
+// before the kernel is booted. Replacing the contents of the section does not change its size, so all addresses stay
+// the same.
+//
+// The table is text, one function per line, sorted by address:
+//
+//     <address: 16 hex digits> <size: 16 hex digits, 0 if unknown> <demangled name>
+//
+// The rest of the section is filled with zeros. A kernel that was not started through `runner.sh` has an empty table.
+
+use core::fmt;
+use core::ptr;
+use core::str;
+use x86_64::VirtAddr;
+
+/// The size of the `.ksyms` section. `runner.sh` fails if the table does not fit.
+const SYMBOL_TABLE_SIZE: usize = 1024 * 1024;
+
+// Mutable, so that the compiler does not assume that the table is empty: it is changed after compilation.
+#[link_section = ".ksyms"]
+static mut SYMBOL_TABLE: [u8; SYMBOL_TABLE_SIZE] = [0; SYMBOL_TABLE_SIZE];
+
+// address, space, size, space
+const NAME_START: usize = 16 + 1 + 16 + 1;
+
+/// A function that contains an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// The start address of the function.
+    pub address: VirtAddr,
+    /// The distance of the address from the start of the function.
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// Returns whether the symbol table was embedded into the kernel.
+pub fn is_available() -> bool {
+    lines().next().is_some()
+}
+
+/// Returns the function that contains the given address, or `None` if the address is not in a known function.
+pub fn lookup(address: VirtAddr) -> Option<Symbol> {
+    let address = address.as_u64();
+    let mut found = None;
+    for (start, size, name) in lines().filter_map(parse_line) {
+        // the lines are sorted by address, so the last function that starts before the address contains it
+        if start > address {
+            break;
+        }
+        found = Some((start, size, name));
+    }
+    let (start, size, name) = found?;
+    let offset = address - start;
+    // functions defined in assembly have no size
+    if size != 0 && offset >= size {
+        return None;
+    }
+    Some(Symbol {
+        name,
+        address: VirtAddr::new(start),
+        offset,
+    })
+}
+
+/// Returns the lines of the symbol table.
+fn lines() -> impl Iterator<Item = &'static [u8]> {
+    // the table is never written at run time, so shared references to it are fine
+    let table: &'static [u8; SYMBOL_TABLE_SIZE] = unsafe { &*ptr::addr_of!(SYMBOL_TABLE) };
+    let len = table.iter().position(|&byte| byte == 0).unwrap_or(table.len());
+    table[..len].split(|&byte| byte == b'\n').filter(|line| !line.is_empty())
+}
+
+/// Splits a line into the address, the size and the name.
+fn parse_line(line: &'static [u8]) -> Option<(u64, u64, &'static str)> {
+    let address = parse_hex(line.get(..16)?)?;
+    let size = parse_hex(line.get(17..33)?)?;
+    let name = str::from_utf8(line.get(NAME_START..)?).ok()?;
+    Some((address, size, name))
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    u64::from_str_radix(str::from_utf8(digits).ok()?, 16).ok()
+}
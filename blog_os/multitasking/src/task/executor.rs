@@ -1,47 +1,106 @@
 use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use crate::allocator::Locked;
+use alloc::{alloc::AllocError, collections::VecDeque, sync::Arc, task::Wake, vec::Vec};
+use core::fmt;
 use core::task::{Waker, Context, Poll};
-use crossbeam_queue::ArrayQueue;
 
 pub struct Executor {
-    // use a task_queue of task IDs and a BTreeMap named tasks that contains the actual Task instances.
-    // The map is indexed by the TaskId to allow efficient continuation of a specific task.
-    tasks: BTreeMap<TaskId, Task>,
+    // use a task_queue of task IDs and a task table named tasks that contains the actual Task instances.
+    // The table is sorted by TaskId to allow efficient continuation of a specific task through a binary search.
+    // Unlike a BTreeMap, a Vec can reserve memory fallibly, so that `try_spawn` can report an out-of-memory error instead of panicking.
+    // Inserting and removing entries shifts the entries behind them, which is O(n) instead of the O(log n) of a BTreeMap. TaskIds
+    // increase, so a new task is usually inserted at the end, and the kernel runs a handful of long-lived tasks, so moving the
+    // entries of the tasks that were spawned after a finished one is cheaper than the tree nodes of a BTreeMap.
+    tasks: Vec<TaskEntry>,
 
     // ArrayQueue: wrapped into the Arc type that implements reference counting
     //             Reference counting makes it possible to share ownership of the value among multiple owners.
     //             It works by allocating the value on the heap and counting the number of active references to it. 
     //             When the number of active references reaches zero, the value is no longer needed and can be deallocated.
-    // Arc<ArrayQueue>: it will be shared between the executor and wakers.
+    // Arc<TaskQueue>: it will be shared between the executor and wakers.
     //                  The wakers push the ID of the woken task to the queue. 
-    //                  The executor sits on the receiving end of the queue, retrieves the woken tasks by their ID from the tasks map, and then runs them.
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    
-    // This map caches the Waker of a task after its creation. This has two reasons:
+    //                  The executor sits on the receiving end of the queue, retrieves the woken tasks by their ID from the tasks table, and then runs them.
+    task_queue: Arc<TaskQueue>,
+}
+
+/// The number of woken tasks that the task queue can hold.
+///
+/// The reason for using a fixed-size queue instead of an unbounded queue such as SegQueue is that interrupt handlers should not allocate on push to this queue.
+/// We choose a capacity of 100 for the task_queue, which should be more than enough for the foreseeable future.
+const TASK_QUEUE_SIZE: usize = 100;
+
+/// A spawned task together with its waker.
+struct TaskEntry {
+    task: Task,
+    // The Waker of a task is created when the task is spawned and cached until the task is done. This has three reasons:
     // 1) it improves performance by reusing the same waker for multiple wake-ups of the same task instead of creating a new waker each time
     // 2) it ensures that reference-counted wakers are not deallocated inside interrupt handlers because it could lead to deadlocks
-    waker_cache: BTreeMap<TaskId, Waker>,
+    // 3) running a task never allocates, so all allocation failures are reported by `try_spawn`
+    waker: Waker,
+}
+
+/// The error returned by `Executor::try_spawn`, which hands the task that could not be spawned back to the caller.
+pub enum SpawnError {
+    /// There was not enough memory for the task table entry or the waker of the task.
+    OutOfMemory(Task),
+    /// The task queue is full.
+    QueueFull(Task),
+}
+
+impl fmt::Debug for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpawnError::OutOfMemory(task) => write!(f, "OutOfMemory({:?})", task.id),
+            SpawnError::QueueFull(task) => write!(f, "QueueFull({:?})", task.id),
+        }
+    }
 }
 
 impl Executor {
+    /// Panics if there is not enough memory for the task queue, see `try_new` for a fallible version.
     pub fn new() -> Self {
-        Executor {
-            tasks: BTreeMap::new(),
-            // The reason for using a fixed-size queue instead of an unbounded queue such as SegQueue is that interrupt handlers should not allocate on push to this queue
-            // We choose a capacity of 100 for the task_queue, which should be more than enough for the foreseeable future.
-            task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
-        }
+        Self::try_new().expect("failed to allocate the task queue")
     }
 
-    /// adds a given task to the tasks map 
+    /// Like `new`, but returns an error if there is not enough memory for the task queue.
+    pub fn try_new() -> Result<Self, AllocError> {
+        let task_queue = TaskQueue::try_new(TASK_QUEUE_SIZE)?;
+        Ok(Executor {
+            tasks: Vec::new(),
+            task_queue: Arc::try_new(task_queue)?,
+        })
+    }
+
+    /// adds a given task to the tasks table 
     /// and immediately wakes it by pushing its ID to the task_queue
+    ///
+    /// Panics if the task cannot be spawned, see `try_spawn` for a fallible version.
     pub fn spawn(&mut self, task: Task) {
+        if let Err(err) = self.try_spawn(task) {
+            panic!("failed to spawn task: {:?}", err);
+        }
+    }
+
+    /// Like `spawn`, but returns the task instead of panicking if there is not enough memory or the task queue is full.
+    pub fn try_spawn(&mut self, task: Task) -> Result<(), SpawnError> {
         let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
+        let index = match self.tasks.binary_search_by_key(&task_id, |entry| entry.task.id) {
+            Ok(_) => panic!("task with same ID already in tasks"),
+            Err(index) => index,
+        };
+        // allocate everything before the task is queued, so that a failure leaves the executor unchanged
+        if self.tasks.try_reserve(1).is_err() {
+            return Err(SpawnError::OutOfMemory(task));
+        }
+        let waker = match TaskWaker::try_new(task_id, self.task_queue.clone()) {
+            Ok(waker) => waker,
+            Err(AllocError) => return Err(SpawnError::OutOfMemory(task)),
+        };
+        if self.task_queue.push(task_id).is_err() {
+            return Err(SpawnError::QueueFull(task));
         }
-        self.task_queue.push(task_id).expect("queue full");
+        self.tasks.insert(index, TaskEntry { task, waker });
+        Ok(())
     }
 
     fn run_ready_tasks(&mut self) {
@@ -49,30 +108,23 @@ impl Executor {
         let Self {
             tasks,
             task_queue,
-            waker_cache,
         } = self;
 
-        // Loop over all tasks in the task_queue, create a waker for each task, and then poll them
-        while let Ok(task_id) = task_queue.pop() {
-            // For each popped task ID, we retrieve a mutable reference to the corresponding task from the tasks map. 
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
+        // Loop over all tasks in the task_queue and poll them with their cached wakers
+        while let Some(task_id) = task_queue.pop() {
+            // For each popped task ID, we look up the position of the corresponding task in the tasks table. 
+            let index = match tasks.binary_search_by_key(&task_id, |entry| entry.task.id) {
+                Ok(index) => index,
                 // Since our ScancodeStream implementation registers wakers before checking whether a task needs to be put to sleep, it might happen that a wake-up occurs for a task that no longer exists.
                 // In this case, we simply ignore the wake-up and continue with the next ID from the queue.
-                None => continue, // task no longer exists
+                Err(_) => continue, // task no longer exists
             };
-            // To avoid the performance overhead of creating a waker on each poll, we use the waker_cache map to store the waker for each task after it has been created.
-            let waker = waker_cache
-                // `entry`+`or_insert_with`: to create a new waker if it doesn’t exist yet and then get a mutable reference to it
-                .entry(task_id)
-                // For creating a new waker, we clone the task_queue and pass it together with the task ID to the TaskWaker::new function (implementation shown below).
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let TaskEntry { task, waker } = &mut tasks[index];
             let mut context = Context::from_waker(waker);
             match task.poll(&mut context) {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cache waker
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+                    // task done -> remove it together with its cached waker
+                    tasks.remove(index);
                 }
                 Poll::Pending => {}
             }
@@ -82,7 +134,12 @@ impl Executor {
     /// Since the function never returns, we use the ! return type to mark the function as diverging to the compiler.
     pub fn run(&mut self) -> ! {
         loop {
+            // wake the tasks whose timers expired since the last iteration
+            super::timer::wake_expired();
             self.run_ready_tasks();
+            // validate the red zones of the heap between task runs, so that corruption is found close to where it happened
+            #[cfg(feature = "alloc-debug")]
+            crate::allocator::debug::check_heap();
             // We no longer poll tasks until they are woken again, but we still check the task_queue in a busy loop.
             // To fix this, we need to put the CPU to sleep if there is no more work to do.
             self.sleep_if_idel();
@@ -107,21 +164,61 @@ impl Executor {
 }
 
 
+/// A fixed-size queue of the IDs of woken tasks.
+///
+/// It replaces crossbeam's ArrayQueue, whose buffer cannot be allocated fallibly. Wakers push to it from interrupt handlers, so
+/// like the allocator it is a `Locked`, which disables interrupts while it is held: a push never waits for the interrupted code.
+struct TaskQueue {
+    queue: Locked<VecDeque<TaskId>>,
+    capacity: usize,
+}
+
+impl TaskQueue {
+    fn try_new(capacity: usize) -> Result<Self, AllocError> {
+        let mut queue = VecDeque::new();
+        queue.try_reserve_exact(capacity).map_err(|_| AllocError)?;
+        Ok(TaskQueue {
+            queue: Locked::new(queue),
+            capacity,
+        })
+    }
+
+    /// Appends a task ID, or returns it if the queue is full. Never allocates.
+    fn push(&self, task_id: TaskId) -> Result<(), TaskId> {
+        let mut queue = self.queue.lock();
+        if queue.len() == self.capacity {
+            return Err(task_id);
+        }
+        queue.push_back(task_id);
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        self.queue.lock().pop_front()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+}
+
 /// The job of the waker is to push the ID of the woken task to the task_queue of the executor. 
 struct TaskWaker {
     task_id: TaskId,
     // Since the ownership of the task_queue is shared between the executor and wakers, we use the Arc wrapper type to implement shared reference-counted ownership
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    task_queue: Arc<TaskQueue>,
 }
 
 impl TaskWaker {
     /// create the TaskWaker using the passed task_id and task_queue
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    ///
+    /// Returns an error if there is not enough memory for the Arc.
+    fn try_new(task_id: TaskId, task_queue: Arc<TaskQueue>) -> Result<Waker, AllocError> {
         // wrap the TaskWaker in an Arc and use the Waker::from implementation to convert it to a Waker.
-        Waker::from(Arc::new(TaskWaker {
+        Ok(Waker::from(Arc::try_new(TaskWaker {
             task_id,
             task_queue,
-        }))
+        })?))
     }
 
     fn wake_task(&self) {
@@ -1,16 +1,69 @@
 use conquer_once::{spin::OnceCell};
-use crossbeam_queue::ArrayQueue;
 use crate::{println, print};
+use alloc::{boxed::Box, collections::TryReserveError, vec::Vec};
 use core::{pin::Pin, task::{Poll, Context}};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use futures_util::stream::{Stream, StreamExt};
 use futures_util::task::AtomicWaker;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
 
 
-static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Number of scancodes that can be buffered until the keyboard task reads them.
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+static SCANCODE_QUEUE: OnceCell<ScancodeQueue> = OnceCell::uninit();
 // Like the Futures::poll method, the Stream::poll_next method requires the asynchronous task to notify the executor when it becomes ready after Poll::Pending is returned.
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+/// A fixed-size ring buffer for scancodes.
+///
+/// It replaces crossbeam's ArrayQueue, whose buffer cannot be allocated fallibly.
+/// The keyboard interrupt handler is the only producer and the ScancodeStream the only consumer, so `head` is only written by `pop` and `tail` only by `push`.
+struct ScancodeQueue {
+    buffer: Box<[AtomicU8]>,
+    // index of the next scancode to pop, wrapping around
+    head: AtomicUsize,
+    // index of the next free slot, wrapping around
+    tail: AtomicUsize,
+}
+
+impl ScancodeQueue {
+    fn try_new(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut buffer = Vec::new();
+        buffer.try_reserve_exact(capacity)?;
+        buffer.resize_with(capacity, || AtomicU8::new(0));
+        Ok(ScancodeQueue {
+            buffer: buffer.into_boxed_slice(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        })
+    }
+
+    /// Appends a scancode, or returns it if the queue is full.
+    fn push(&self, scancode: u8) -> Result<(), u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == self.buffer.len() {
+            return Err(scancode);
+        }
+        self.buffer[tail % self.buffer.len()].store(scancode, Ordering::Relaxed);
+        // publish the scancode only after it was written
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the oldest scancode.
+    fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let scancode = self.buffer[head % self.buffer.len()].load(Ordering::Relaxed);
+        // release the slot only after it was read
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(scancode)
+    }
+}
+
 /// Called by the keyboard interrupt handler
 ///
 /// Must not block or allocate.
@@ -18,8 +71,8 @@ static WAKER: AtomicWaker = AtomicWaker::new();
 pub(crate) fn add_scancode(scancode: u8) {
     // use OnceCell::try_get to get a reference to the initialized queue.
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        // the ArrayQueue::push method requires only a &self reference makes it very simple to call the method on the static queue.
-        // The ArrayQueue type performs all the necessary synchronization itself, so we don’t need a mutex wrapper here.
+        // the ScancodeQueue::push method requires only a &self reference makes it very simple to call the method on the static queue.
+        // The ScancodeQueue type performs all the necessary synchronization itself, so we don’t need a mutex wrapper here.
         if let Err(_) = queue.push(scancode) {
             // In case the queue is full, we print a warning too.
             // we call wake only after pushing to the queue because otherwise the task might be woken too early while the queue is still empty
@@ -44,11 +97,25 @@ pub struct ScancodeStream {
 
 impl ScancodeStream {
     pub fn new() -> Self {
+        Self::try_new().expect("failed to allocate the scancode queue")
+    }
+
+    /// Like `new`, but returns an error if there is not enough memory for the scancode queue.
+    ///
+    /// Keyboard input is ignored until a ScancodeStream was created successfully.
+    pub fn try_new() -> Result<Self, TryReserveError> {
+        let queue = ScancodeQueue::try_new(SCANCODE_QUEUE_SIZE)?;
         // initialize the SCANCODE_QUEUE static
-        SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(100))
+        SCANCODE_QUEUE.try_init_once(|| queue)
         // panic if it is already initialized to ensure that only a single ScancodeStream instance can be created.
             .expect("ScancodeStream::new should only be called once");
-        ScancodeStream { _private: () }
+        Ok(ScancodeStream { _private: () })
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -60,8 +127,8 @@ impl Stream for ScancodeStream {
             // use the `OnceCell::try_ge` method to get a reference to the initialized scancode queue.
             .try_get()
             .expect("not initialized");
-        // use the `ArrayQueue::pop` method to try to get the next element from the queue.
-        if let Ok(scancode) = queue.pop() {
+        // use the `ScancodeQueue::pop` method to try to get the next element from the queue.
+        if let Some(scancode) = queue.pop() {
             // If it succeeds, we return the scancode wrapped in Poll::Ready(Some(…))
             return Poll::Ready(Some(scancode));
         }
@@ -73,28 +140,47 @@ impl Stream for ScancodeStream {
         // This way, a wakeup might happen before we return Poll::Pending, but it is guaranteed that we get a wakeup for any scancodes pushed after the check.
         WAKER.register(&cx.waker());
         match queue.pop() {
-            Ok(scancode) => {
+            Some(scancode) => {
                 // remove the registered waker again using AtomicWaker::take because a waker notification is no longer needed.
                 WAKER.take();
                 Poll::Ready(Some(scancode))
             }
-            Err(crossbeam_queue::PopError) => Poll::Pending,
+            None => Poll::Pending,
         }
     }
 }
 
 /// create an asynchronous keyboard task:
 pub async fn print_keypresses() {
-    let mut scancode = ScancodeStream::new();
+    let mut scancode = match ScancodeStream::try_new() {
+        Ok(stream) => stream,
+        Err(_) => {
+            // the rest of the kernel keeps running without keyboard input
+            println!("WARNING: out of memory, keyboard input disabled");
+            return;
+        }
+    };
     let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, 
         HandleControl::Ignore);
+    // the keyboard does not expose its modifier state, so Ctrl and Alt are tracked here for Ctrl+Alt+Del
+    let mut ctrl = false;
+    let mut alt = false;
 
     // The code is very similar to the code we had in our keyboard interrupt handler before we modified it in this post. 
     // The only difference is that, instead of reading the scancode from an I/O port, we take it from the ScancodeStream. 
     while let Some(scancode) = scancode.next().await {
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            let down = key_event.state == KeyState::Down;
+            match key_event.code {
+                KeyCode::ControlLeft | KeyCode::ControlRight => ctrl = down,
+                KeyCode::AltLeft | KeyCode::AltRight => alt = down,
+                KeyCode::Delete if down && ctrl && alt => crate::power::reboot(),
+                _ => {}
+            }
             if let Some(key) = keyboard.process_keyevent(key_event) {
                 match key {
+                    // debug key: dump the heap statistics
+                    DecodedKey::RawKey(KeyCode::F12) => println!("\n{}", crate::allocator::stats()),
                     DecodedKey::Unicode(character) => print!("{}", character),
                     DecodedKey::RawKey(key) => print!("{:?}", key),
                 }
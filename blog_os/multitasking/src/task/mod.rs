@@ -1,11 +1,13 @@
 use core::{future::Future, pin::Pin,};
 use core::task::{Context, Poll};
 use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::alloc::AllocError;
 use alloc::boxed::Box;
 
 pub mod simple_executor;
 pub mod keyboard;
 pub mod executor;
+pub mod timer;
 
 /// The Task struct is a newtype wrapper around a pinned, heap-allocated, and dynamically dispatched future with the empty type () as output.
 /// 
@@ -30,6 +32,14 @@ impl Task {
         }
     }
 
+    /// Like `new`, but returns an error instead of calling the `alloc_error_handler` if the future cannot be allocated.
+    pub fn try_new(future: impl Future<Output = ()> + 'static) -> Result<Task, AllocError> {
+        Ok(Task {
+            id: TaskId::new(),
+            future: Box::into_pin(Box::try_new(future)?),
+        })
+    }
+
     /// to allow the executor to poll the stored future
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         // 1. we use the Pin::as_mut method to convert the self.future field of type Pin<Box<T>>
@@ -0,0 +1,105 @@
+use crate::time::Instant;
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use futures_util::future::{self, Either};
+use futures_util::pin_mut;
+use spin::Mutex;
+
+// The pending timers, ordered by their deadline. The ID makes the keys unique when several timers have the same deadline.
+// The timer interrupt handler never touches this map: it only advances the clock of the `time` module and wakes the CPU from `hlt`.
+// The executor then calls `wake_expired` outside of the interrupt handler, so no waker is woken or dropped in interrupt context.
+static TIMERS: Mutex<BTreeMap<(Instant, u64), Waker>> = Mutex::new(BTreeMap::new());
+
+/// Wakes all tasks whose timers have expired.
+///
+/// The executor calls this every time it wakes up, which happens at least on every timer interrupt.
+pub fn wake_expired() {
+    let now = Instant::now();
+    let mut timers = TIMERS.lock();
+    while let Some(entry) = timers.first_entry() {
+        if entry.key().0 > now {
+            break;
+        }
+        entry.remove().wake();
+    }
+}
+
+/// A future that completes once the given instant is reached, created by `sleep` and `sleep_until`.
+pub struct Sleep {
+    deadline: Instant,
+    id: u64,
+    // whether a waker is registered in TIMERS
+    registered: bool,
+}
+
+/// Returns a future that completes after (at least) the given duration.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now().saturating_add(duration))
+}
+
+/// Returns a future that completes once `deadline` is reached.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    Sleep {
+        deadline,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        registered: false,
+    }
+}
+
+impl Sleep {
+    /// Returns the instant at which the future completes.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        if Instant::now() >= this.deadline {
+            return Poll::Ready(());
+        }
+
+        // Like the ScancodeStream, register the waker before checking again, so that a timer interrupt between the check
+        // and the registration is not missed. Re-registering replaces the waker of a previous poll.
+        TIMERS.lock().insert((this.deadline, this.id), cx.waker().clone());
+        this.registered = true;
+        if Instant::now() >= this.deadline {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // a timer that is dropped before it expired (e.g. by `timeout`) must not keep its waker alive
+        if self.registered {
+            TIMERS.lock().remove(&(self.deadline, self.id));
+        }
+    }
+}
+
+/// The error returned by `timeout` when the duration elapsed before the future completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Runs the given future until it completes or the given duration elapses, whichever comes first.
+///
+/// If the duration elapses first, the future is dropped and `Err(Elapsed)` is returned.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    let sleep = sleep(duration);
+    pin_mut!(future);
+    match future::select(future, sleep).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(((), _)) => Err(Elapsed),
+    }
+}
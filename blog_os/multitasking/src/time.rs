@@ -0,0 +1,247 @@
+// Monotonic time since boot.
+//
+// The coarse clock counts the timer interrupts (ticks), which `init` programs to `TICK_RATE`. They are raised by the PIT
+// until `interrupts::apic::init` switches to the local APIC timer. Between two
+// ticks, the time is interpolated with the time stamp counter (TSC): every tick records the TSC, and the number of TSC
+// cycles per tick is measured from the last two ticks. The interpolation never reaches the next tick, so the clock
+// does not go backwards when the next tick arrives.
+//
+// A new tick rate only applies after the period in progress: the tick source finishes it with the old length, so the
+// first tick after a change still counts as an old tick, and the new length is used from there on.
+
+use crate::{interrupts::apic, pit};
+use core::arch::x86_64::_rdtsc;
+use core::convert::TryFrom;
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+/// Tick rate that `init` programs into the PIT, in Hz.
+pub const TICK_RATE: u32 = 1000;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+// number of timer interrupts since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// the TSC at the last tick (0 if unknown) and the number of TSC cycles between the last two ticks (0 if unknown)
+static LAST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+static TSC_PER_TICK: AtomicU64 = AtomicU64::new(0);
+// The tick length changes with the tick rate, so the ticks are counted from the last change of the rate:
+// the time and the number of ticks at that point.
+static BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+static BASE_TICKS: AtomicU64 = AtomicU64::new(0);
+// The length of a tick: a number of cycles of the tick source, which runs at the given frequency in Hz.
+static CYCLES_PER_TICK: AtomicU64 = AtomicU64::new(65536);
+static SOURCE_FREQUENCY: AtomicU64 = AtomicU64::new(pit::BASE_FREQUENCY as u64);
+// the tick length after a change of the rate, which the next tick applies (0 if the rate did not change)
+static PENDING_CYCLES_PER_TICK: AtomicU64 = AtomicU64::new(0);
+static PENDING_SOURCE_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+// the requested tick rate and whether the local APIC timer is the tick source
+static TICK_RATE_REQUESTED: AtomicU32 = AtomicU32::new(TICK_RATE);
+static LOCAL_APIC_SOURCE: AtomicBool = AtomicBool::new(false);
+// the latest time returned by `Instant::now`
+static LAST_NOW: AtomicU64 = AtomicU64::new(0);
+
+/// The hardware timer that raises `InterruptIndex::Timer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    Pit,
+    LocalApic,
+}
+
+/// Programs the PIT to `TICK_RATE`.
+pub fn init() {
+    set_tick_rate(TICK_RATE);
+}
+
+/// Returns the hardware timer that raises the timer interrupt.
+pub fn tick_source() -> TickSource {
+    if LOCAL_APIC_SOURCE.load(Ordering::Relaxed) {
+        TickSource::LocalApic
+    } else {
+        TickSource::Pit
+    }
+}
+
+/// Switches to another tick source, which is programmed to the current tick rate.
+pub(crate) fn set_tick_source(source: TickSource) {
+    LOCAL_APIC_SOURCE.store(source == TickSource::LocalApic, Ordering::Relaxed);
+    set_tick_rate(TICK_RATE_REQUESTED.load(Ordering::Relaxed));
+}
+
+/// Changes the tick rate and returns the rate that the tick source actually runs at.
+///
+/// The clock keeps running across the change. The new rate applies after the next tick, which ends the period that is
+/// in progress.
+pub fn set_tick_rate(frequency: u32) -> u32 {
+    interrupts::without_interrupts(|| {
+        TICK_RATE_REQUESTED.store(frequency, Ordering::Relaxed);
+        let (cycles, source_frequency) = match tick_source() {
+            TickSource::Pit => {
+                pit::set_frequency(frequency);
+                (u64::from(pit::divisor()), u64::from(pit::BASE_FREQUENCY))
+            }
+            TickSource::LocalApic => apic::set_timer_frequency(frequency),
+        };
+        if TICKS.load(Ordering::Relaxed) == 0 {
+            // no time was counted yet, e.g. when `init` replaces the BIOS rate, so the new length applies right away
+            CYCLES_PER_TICK.store(cycles, Ordering::Relaxed);
+            SOURCE_FREQUENCY.store(source_frequency, Ordering::Relaxed);
+        } else {
+            PENDING_SOURCE_FREQUENCY.store(source_frequency, Ordering::Relaxed);
+            PENDING_CYCLES_PER_TICK.store(cycles, Ordering::Relaxed);
+        }
+        ((source_frequency + cycles / 2) / cycles) as u32
+    })
+}
+
+/// Returns the length of a tick in nanoseconds (rounded down).
+pub fn tick_nanos() -> u64 {
+    (u128::from(CYCLES_PER_TICK.load(Ordering::Relaxed)) * NANOS_PER_SECOND
+        / u128::from(SOURCE_FREQUENCY.load(Ordering::Relaxed))) as u64
+}
+
+/// Called by the timer interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn tick() {
+    let tsc = rdtsc();
+    let last = LAST_TICK_TSC.swap(tsc, Ordering::Relaxed);
+    let pending_cycles = PENDING_CYCLES_PER_TICK.swap(0, Ordering::Relaxed);
+    if pending_cycles != 0 {
+        // this tick ends the last period of the old length, so the ticks are counted from here with the new length
+        let ticks = TICKS.load(Ordering::Relaxed) + 1;
+        let nanos = BASE_NANOS.load(Ordering::Relaxed) + ticks_to_nanos(ticks - BASE_TICKS.load(Ordering::Relaxed));
+        BASE_NANOS.store(nanos, Ordering::Relaxed);
+        BASE_TICKS.store(ticks, Ordering::Relaxed);
+        CYCLES_PER_TICK.store(pending_cycles, Ordering::Relaxed);
+        SOURCE_FREQUENCY.store(PENDING_SOURCE_FREQUENCY.load(Ordering::Relaxed), Ordering::Relaxed);
+        // the TSC cycles per tick need to be measured again
+        TSC_PER_TICK.store(0, Ordering::Relaxed);
+    } else if last != 0 {
+        TSC_PER_TICK.store(tsc.wrapping_sub(last), Ordering::Relaxed);
+    }
+    // incremented last, so that `uptime_nanos` can detect a tick that happened while it read the other values
+    TICKS.fetch_add(1, Ordering::Release);
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since boot.
+pub fn uptime() -> Duration {
+    Instant::now().since_boot()
+}
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Converts a number of ticks of the current length into nanoseconds.
+fn ticks_to_nanos(ticks: u64) -> u64 {
+    (u128::from(ticks) * u128::from(CYCLES_PER_TICK.load(Ordering::Relaxed)) * NANOS_PER_SECOND
+        / u128::from(SOURCE_FREQUENCY.load(Ordering::Relaxed))) as u64
+}
+
+fn uptime_nanos() -> u64 {
+    loop {
+        let ticks = TICKS.load(Ordering::Acquire);
+        let base_ticks = BASE_TICKS.load(Ordering::Relaxed);
+        let base_nanos = BASE_NANOS.load(Ordering::Relaxed);
+        let elapsed = ticks_to_nanos(ticks.wrapping_sub(base_ticks));
+        let period = tick_nanos();
+        let last_tick_tsc = LAST_TICK_TSC.load(Ordering::Relaxed);
+        let tsc_per_tick = TSC_PER_TICK.load(Ordering::Relaxed);
+        let tsc = rdtsc();
+        if TICKS.load(Ordering::Acquire) != ticks {
+            // a tick happened in between, so the values above do not belong together
+            continue;
+        }
+
+        let mut nanos = u128::from(base_nanos) + u128::from(elapsed);
+        if last_tick_tsc != 0 && tsc_per_tick != 0 {
+            let period = u128::from(period);
+            let fraction = u128::from(tsc.wrapping_sub(last_tick_tsc)) * period / u128::from(tsc_per_tick);
+            nanos += fraction.min(period - 1);
+        }
+        return nanos as u64;
+    }
+}
+
+/// A point in time, measured in nanoseconds since boot. Like `std::time::Instant`, it never goes backwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the current time.
+    pub fn now() -> Instant {
+        let nanos = uptime_nanos();
+        // the TSC rate is only an estimate, so a new tick can start slightly before the previous interpolated time
+        Instant(LAST_NOW.fetch_max(nanos, Ordering::Relaxed).max(nanos))
+    }
+
+    /// Returns the time between boot and this instant.
+    pub fn since_boot(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+
+    /// Returns the time elapsed from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the time elapsed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the instant `duration` after this one, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(Instant)
+    }
+
+    /// Returns the instant `duration` before this one, or `None` if that would be before boot.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_sub(nanos).map(Instant)
+    }
+
+    /// Like `checked_add`, but returns the latest representable instant on overflow.
+    pub fn saturating_add(&self, duration: Duration) -> Instant {
+        self.checked_add(duration).unwrap_or(Instant(u64::MAX))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Runs the heap_allocation integration test once for every global allocator that can be selected through the `alloc-*` features.
+set -e
+
+for allocator in alloc-bump alloc-linked-list alloc-fixed-block; do
+    echo "== $allocator"
+    cargo test --no-default-features --features "$allocator" --test heap_allocation
+done
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use blog_os::acpi::{self, mcfg::ConfigSpace, AcpiTables, Mcfg};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let tables = unsafe { acpi::init(phys_mem_offset) }.expect("ACPI initialization failed");
+    // the parsed tables stay available without calling `init` again
+    assert!(core::ptr::eq(tables, acpi::tables().unwrap()));
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn tables() -> &'static AcpiTables {
+    acpi::tables().expect("ACPI tables not initialized")
+}
+
+#[test_case]
+fn root_table_lists_madt() {
+    let madt = tables().madt.as_ref().expect("no MADT");
+    assert!(!madt.local_apics.is_empty());
+}
+
+#[test_case]
+fn fadt_describes_power_management() {
+    let fadt = tables().fadt.as_ref().expect("no FADT");
+    assert_ne!(fadt.pm1a_control_block, 0);
+    assert!(fadt.has_8042());
+
+    // the DSDT is validated like every other table
+    let dsdt = tables().acpi.table(fadt.dsdt).unwrap();
+    assert_eq!(&dsdt[..4], b"DSDT");
+}
+
+#[test_case]
+fn hpet_at_standard_address() {
+    let hpet = tables().hpet.as_ref().expect("no HPET");
+    assert_eq!(hpet.base_address, PhysAddr::new(0xfed0_0000));
+    assert!(hpet.comparators >= 3);
+}
+
+#[test_case]
+fn unknown_table_is_not_found() {
+    assert_eq!(
+        tables().acpi.find_table(b"XXXX"),
+        Err(acpi::AcpiError::TableNotFound(*b"XXXX"))
+    );
+}
+
+#[test_case]
+fn mcfg_config_address() {
+    let mcfg = Mcfg {
+        entries: vec![ConfigSpace {
+            base_address: PhysAddr::new(0xb000_0000),
+            segment_group: 0,
+            start_bus: 0,
+            end_bus: 0xff,
+        }],
+    };
+    assert_eq!(mcfg.config_address(0, 0, 0, 0), Some(PhysAddr::new(0xb000_0000)));
+    assert_eq!(
+        mcfg.config_address(0, 1, 2, 3),
+        Some(PhysAddr::new(0xb000_0000 + (1 << 20) + (2 << 15) + (3 << 12)))
+    );
+    assert_eq!(mcfg.config_address(1, 0, 0, 0), None);
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::acpi;
+use blog_os::interrupts::apic;
+use blog_os::time::{self, TickSource};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { apic::init(phys_mem_offset) }.expect("APIC initialization failed");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_describes_qemu() {
+    // `apic::init` already parsed the tables
+    let madt = acpi::tables().unwrap().madt.as_ref().unwrap();
+    assert!(!madt.local_apics.is_empty());
+    assert_eq!(madt.io_apics.len(), 1);
+    assert!(madt.has_8259_pics);
+    // QEMU connects the PIT to GSI 2
+    assert_eq!(madt.isa_irq(0).0, 2);
+}
+
+#[test_case]
+fn local_apic_timer_drives_clock() {
+    assert!(apic::is_enabled());
+    assert_eq!(time::tick_source(), TickSource::LocalApic);
+    let start = time::ticks();
+    while time::ticks() < start + 10 {
+        x86_64::instructions::hlt();
+    }
+}
+
+// the local APIC timer is programmed to the same tick rate as the PIT before
+#[test_case]
+fn tick_rate_is_kept() {
+    let tick = time::tick_nanos();
+    assert!(tick > 990_000 && tick < 1_010_000, "{} ns per tick", tick);
+}
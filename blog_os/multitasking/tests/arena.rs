@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(allocator_api)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use blog_os::allocator::{self, arena::{Arena, CHUNK_SIZE}};
+use blog_os::task::{simple_executor::SimpleExecutor, Task};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// small allocations share a chunk
+#[test_case]
+fn small_allocations_share_a_chunk() {
+    let arena = Arena::new();
+    let boxes: Vec<Box<u64, &Arena>> = (0..100).map(|i| Box::new_in(i, &arena)).collect();
+    for (i, value) in boxes.iter().enumerate() {
+        assert_eq!(**value, i as u64);
+    }
+    assert_eq!(arena.capacity(), CHUNK_SIZE);
+}
+
+// a Vec that is filled in a loop grows in place instead of leaving its old buffers behind
+#[test_case]
+fn vec_grows_in_place() {
+    let arena = Arena::new();
+    let mut vec = Vec::new_in(&arena);
+    for i in 0..200u64 {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (0..200).sum());
+    assert_eq!(arena.capacity(), CHUNK_SIZE);
+}
+
+// allocations that are larger than a chunk get a chunk of their own
+#[test_case]
+fn large_allocation() {
+    let arena = Arena::new();
+    let vec: Vec<u8, &Arena> = Vec::with_capacity_in(3 * CHUNK_SIZE, &arena);
+    assert!(vec.capacity() >= 3 * CHUNK_SIZE);
+    assert_eq!(arena.capacity(), 4 * CHUNK_SIZE);
+}
+
+// an arena used by a task gives all of its memory back when the task completes
+#[test_case]
+fn task_arena_is_freed() {
+    allocator::leak_check_begin();
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async {
+        let arena = Arena::new();
+        let mut scratch = Vec::new_in(&arena);
+        for i in 0..1000u64 {
+            scratch.push(Box::new_in(i, &arena));
+        }
+        assert_eq!(scratch.iter().map(|value| **value).sum::<u64>(), (0..1000).sum());
+    }));
+    executor.run();
+    drop(executor);
+    allocator::leak_check_end();
+}
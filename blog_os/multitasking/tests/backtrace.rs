@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::backtrace;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    // the walk checks the frames against the page tables, which needs the physical memory offset
+    unsafe { blog_os::memory::init(VirtAddr::new(boot_info.physical_memory_offset)) };
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+const CAPACITY: usize = 8;
+
+type Level = fn(&mut [u64; CAPACITY]) -> usize;
+
+#[inline(never)]
+fn outer(frames: &mut [u64; CAPACITY]) -> usize {
+    middle(frames)
+}
+
+#[inline(never)]
+fn middle(frames: &mut [u64; CAPACITY]) -> usize {
+    inner(frames)
+}
+
+#[inline(never)]
+fn inner(frames: &mut [u64; CAPACITY]) -> usize {
+    let mut count = 0;
+    backtrace::trace(|address| {
+        if count < CAPACITY {
+            frames[count] = address.as_u64();
+        }
+        count += 1;
+    });
+    count
+}
+
+/// Returns whether `address` is a return address within the function at `function`.
+fn within(address: u64, function: u64) -> bool {
+    address > function && address - function < 0x1000
+}
+
+#[test_case]
+fn trace_finds_callers() {
+    let mut frames = [0; CAPACITY];
+    let count = outer(&mut frames);
+    assert!(count >= 3, "only {} frames", count);
+    assert!(within(frames[0], inner as Level as usize as u64));
+    assert!(within(frames[1], middle as Level as usize as u64));
+    assert!(within(frames[2], outer as Level as usize as u64));
+}
+
+#[test_case]
+fn unmapped_frame_pointer() {
+    // the last page of the lower half, which no part of the kernel uses
+    const UNMAPPED: u64 = 0x7fff_ffff_f000;
+    let mut count = 0;
+    backtrace::walk(UNMAPPED, |_| count += 1);
+    // not canonical
+    backtrace::walk(0x8000_0000_0000_0000, |_| count += 1);
+    // not aligned
+    backtrace::walk(UNMAPPED + 3, |_| count += 1);
+    assert_eq!(count, 0);
+}
+
+#[test_case]
+fn frame_pointer_cycle() {
+    // a frame whose saved RBP points to itself
+    let mut frame = [0u64, 0x1234];
+    frame[0] = frame.as_ptr() as u64;
+    let mut count = 0;
+    backtrace::walk(frame.as_ptr() as u64, |address| {
+        assert_eq!(address.as_u64(), 0x1234);
+        count += 1;
+    });
+    assert_eq!(count, 1);
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use blog_os::memory::buddy::{BuddyFrameAllocator, MAX_ORDER};
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Size4KiB};
+
+entry_point!(main);
+
+// the test cases take no arguments, so the allocator under test is shared through a static
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// every block is aligned to its own size
+#[test_case]
+fn blocks_are_aligned() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    for order in 0..=9 {
+        let frame = allocator.allocate_contiguous(order).expect("out of memory");
+        assert_eq!(frame.start_address().as_u64() % (4096 << order), 0);
+        unsafe { allocator.deallocate_contiguous(frame, order) };
+    }
+}
+
+// splitting a block and freeing all parts merges them back together
+#[test_case]
+fn split_and_merge() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+
+    let block = allocator.allocate_contiguous(MAX_ORDER).expect("out of memory");
+    unsafe { allocator.deallocate_contiguous(block, MAX_ORDER) };
+    let frames: [_; 4] = core::array::from_fn(|_| {
+        FrameAllocator::<Size4KiB>::allocate_frame(allocator).expect("out of memory")
+    });
+    assert_eq!(allocator.free_frames(), free_before - 4);
+    for frame in frames {
+        unsafe { allocator.deallocate_frame(frame) };
+    }
+    assert_eq!(allocator.free_frames(), free_before);
+
+    // the largest block must be available again after merging
+    let again = allocator.allocate_contiguous(MAX_ORDER).expect("blocks were not merged");
+    unsafe { allocator.deallocate_contiguous(again, MAX_ORDER) };
+}
+
+// orders above MAX_ORDER cannot be satisfied
+#[test_case]
+fn order_too_large() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    assert!(allocator.allocate_contiguous(MAX_ORDER + 1).is_none());
+}
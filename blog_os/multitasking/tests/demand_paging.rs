@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{self, region::{self, Backing, Region, RegionError}};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::ptr;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// every test case uses its own part of this otherwise unused range
+const AREA_START: u64 = 0x_6666_0000_0000;
+
+fn anonymous(start: u64, pages: u64, flags: PageTableFlags) -> Region {
+    Region {
+        start: VirtAddr::new(AREA_START + start),
+        len: pages * 4096,
+        flags,
+        backing: Backing::Anonymous,
+    }
+}
+
+fn free_frames() -> usize {
+    memory::with_global(|_, frame_allocator| frame_allocator.free_frames()).unwrap()
+}
+
+#[test_case]
+fn mapped_on_first_access() {
+    let region = anonymous(0, 4, PageTableFlags::WRITABLE);
+    region::register(region).unwrap();
+    let last_page = region.start + 3 * 4096u64;
+    assert!(!memory::is_mapped(region.start));
+    assert!(!memory::is_mapped(last_page));
+
+    // the first read maps a zeroed page
+    let ptr = last_page.as_mut_ptr::<u64>();
+    assert_eq!(unsafe { ptr::read_volatile(ptr) }, 0);
+    assert!(memory::is_mapped(last_page));
+    assert!(!memory::is_mapped(region.start));
+
+    // the first write maps a page as well
+    let ptr = region.start.as_mut_ptr::<u64>();
+    unsafe { ptr::write_volatile(ptr.add(100), 0xdead_beef) };
+    assert_eq!(unsafe { ptr::read_volatile(ptr.add(100)) }, 0xdead_beef);
+    assert_eq!(unsafe { ptr::read_volatile(ptr) }, 0);
+
+    assert_eq!(region::unregister(region.start), Some(region));
+    assert!(!memory::is_mapped(region.start));
+    assert!(!memory::is_mapped(last_page));
+}
+
+#[test_case]
+fn frames_freed_on_unregister() {
+    let region = anonymous(0x10_0000, 2, PageTableFlags::WRITABLE);
+    // the first access may allocate page table frames, which stay, so the counts are compared after it
+    region::register(region).unwrap();
+    unsafe { ptr::write_volatile(region.start.as_mut_ptr::<u8>(), 1) };
+    region::unregister(region.start).unwrap();
+
+    let free = free_frames();
+    region::register(region).unwrap();
+    for page in 0..2u64 {
+        unsafe { ptr::write_volatile((region.start + page * 4096).as_mut_ptr::<u8>(), 1) };
+    }
+    assert_eq!(free_frames(), free - 2);
+    region::unregister(region.start).unwrap();
+    assert_eq!(free_frames(), free);
+}
+
+#[test_case]
+fn read_only_region() {
+    let region = anonymous(0x20_0000, 1, PageTableFlags::empty());
+    region::register(region).unwrap();
+    assert_eq!(unsafe { ptr::read_volatile(region.start.as_ptr::<u64>()) }, 0);
+    assert!(memory::is_mapped(region.start));
+    region::unregister(region.start).unwrap();
+}
+
+#[test_case]
+fn invalid_regions() {
+    let region = anonymous(0x30_0000, 2, PageTableFlags::WRITABLE);
+    region::register(region).unwrap();
+
+    // overlapping the start, the end, or the complete region
+    for &(start, pages) in &[(0x2f_f000, 2), (0x30_1000, 2), (0x2f_f000, 4)] {
+        let other = anonymous(start, pages, PageTableFlags::WRITABLE);
+        assert_eq!(region::register(other), Err(RegionError::Overlap(region)));
+    }
+    // directly before and after it is fine
+    let before = anonymous(0x2f_f000, 1, PageTableFlags::WRITABLE);
+    let after = anonymous(0x30_2000, 1, PageTableFlags::WRITABLE);
+    region::register(before).unwrap();
+    region::register(after).unwrap();
+
+    let mut unaligned = anonymous(0x40_0000, 1, PageTableFlags::WRITABLE);
+    unaligned.len = 100;
+    assert_eq!(region::register(unaligned), Err(RegionError::Unaligned));
+    assert_eq!(region::register(anonymous(0x40_0000, 0, PageTableFlags::WRITABLE)), Err(RegionError::Unaligned));
+
+    assert_eq!(region::find(region.start + 4097u64), Some(region));
+    assert_eq!(region::find(region.end()), Some(after));
+    assert_eq!(region::find(VirtAddr::new(AREA_START + 0x40_0000)), None);
+
+    for r in &[region, before, after] {
+        region::unregister(r.start).unwrap();
+    }
+    assert_eq!(region::unregister(region.start), None);
+}
+
+#[test_case]
+fn allocate_keeps_guard_pages() {
+    let area = VirtAddr::new(AREA_START + 0x50_0000)..VirtAddr::new(AREA_START + 0x60_0000);
+    // a region that ends exactly at the start of the area
+    let below = anonymous(0x4f_f000, 1, PageTableFlags::WRITABLE);
+    region::register(below).unwrap();
+
+    let first = region::allocate(area.clone(), 4096, PageTableFlags::WRITABLE, Backing::Anonymous).unwrap();
+    assert_eq!(first.start, area.start + 4096u64);
+    let second = region::allocate(area.clone(), 4096, PageTableFlags::WRITABLE, Backing::Anonymous).unwrap();
+    assert_eq!(second.start, first.end() + 4096u64);
+
+    assert_eq!(
+        region::allocate(area.clone(), u64::MAX - 4095, PageTableFlags::WRITABLE, Backing::Anonymous),
+        Err(RegionError::NoSpace)
+    );
+    assert_eq!(
+        region::allocate(area, 0x10_0000, PageTableFlags::WRITABLE, Backing::Anonymous),
+        Err(RegionError::NoSpace)
+    );
+
+    for r in &[below, first, second] {
+        region::unregister(r.start).unwrap();
+    }
+}
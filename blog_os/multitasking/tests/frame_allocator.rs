@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use blog_os::memory::bitmap::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+entry_point!(main);
+
+// the test cases take no arguments, so the allocator under test is shared through a static
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// two allocations must never return the same frame
+#[test_case]
+fn distinct_frames() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let frame_1 = allocator.allocate_frame().expect("out of frames");
+    let frame_2 = allocator.allocate_frame().expect("out of frames");
+    assert_ne!(frame_1, frame_2);
+    unsafe {
+        allocator.deallocate_frame(frame_1);
+        allocator.deallocate_frame(frame_2);
+    }
+}
+
+// a freed frame is handed out again instead of being leaked
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+    let frame = allocator.allocate_frame().expect("out of frames");
+    assert_eq!(allocator.free_frames(), free_before - 1);
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.free_frames(), free_before);
+    assert_eq!(allocator.allocate_frame(), Some(frame));
+    unsafe { allocator.deallocate_frame(frame) };
+}
+
+// allocate and free many more frames than the heap would ever need
+#[test_case]
+fn many_frames() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+    for _ in 0..100_000 {
+        let frame = allocator.allocate_frame().expect("out of frames");
+        unsafe { allocator.deallocate_frame(frame) };
+    }
+    assert_eq!(allocator.free_frames(), free_before);
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+use blog_os::memory::{self, stack, vmalloc};
+use blog_os::{gdt, serial_print};
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+const EXPECTED: &str = "EXCEPTION: DOUBLE FAULT: stack overflow\n";
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("guard_page::guard_page...\t");
+
+    blog_os::test_init(boot_info);
+
+    // the IST stacks move to the vmalloc area
+    gdt::init_stacks().expect("IST stack allocation failed");
+    let double_fault_stack = gdt::ist_stack(gdt::DOUBLE_FAULT_IST_INDEX).as_u64();
+    assert!(vmalloc::is_vmalloc_address(VirtAddr::new(double_fault_stack) - 1u64));
+
+    // only the stack itself is mapped
+    let stack = stack::allocate_stack(2).expect("stack allocation failed");
+    assert_eq!(stack.top() - stack.bottom(), 2 * 4096);
+    assert!(memory::is_mapped(stack.bottom()));
+    assert!(memory::is_mapped(stack.top() - 1u64));
+    assert!(!memory::is_mapped(stack.guard_page().start_address()));
+    assert!(stack::is_guard_page(stack.bottom() - 1u64));
+    assert!(!stack::is_guard_page(stack.bottom()));
+
+    // run into the guard page
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "call {recurse}",
+            top = in(reg) stack.top().as_u64(),
+            recurse = sym recurse,
+            options(noreturn),
+        )
+    }
+}
+
+extern "C" fn recurse() -> ! {
+    unsafe { asm!("push 0", "call {}", sym recurse, options(noreturn)) }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::expect_panic_message(info, EXPECTED)
+}
@@ -11,22 +11,12 @@ use core::panic::PanicInfo;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 use blog_os::allocator::HEAP_SIZE;
+use blog_os::serial_println;
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use blog_os::allocator;
-    use blog_os::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
-
-    blog_os::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        BootInfoFrameAllocator::init(&boot_info.memory_map)
-    };
-    allocator::init_heap(&mut mapper, &mut frame_allocator)
-        .expect("heap initialization failed");
+    blog_os::test_init(boot_info);
 
     test_main();
     blog_os::hlt_loop();
@@ -37,43 +27,112 @@ fn panic(info: &PanicInfo) -> ! {
     blog_os::test_panic_handler(info)
 }
 
+// runs a test between `leak_check_begin` and `leak_check_end`, so that it fails if it does not free all of its allocations
+fn without_leaks(test: impl FnOnce()) {
+    blog_os::allocator::leak_check_begin();
+    test();
+    blog_os::allocator::leak_check_end();
+}
+
 // performs some simple allocations using Box and checks the allocated values to ensure that basic allocations work
 #[test_case]
 fn simple_allocation() {
-    let heap_value_1 = Box::new(41);
-    let heap_value_2 = Box::new(13);
-    assert_eq!(*heap_value_1, 41);
-    assert_eq!(*heap_value_2, 13);
+    without_leaks(|| {
+        let heap_value_1 = Box::new(41);
+        let heap_value_2 = Box::new(13);
+        assert_eq!(*heap_value_1, 41);
+        assert_eq!(*heap_value_2, 13);
+    });
 }
 
 // iteratively build a large vector, to test both large allocations and multiple allocations (due to reallocations)
 #[test_case]
 fn large_vec() {
-    let n = 1000;
-    let mut vec = Vec::new();
-    for i in 0..n {
-        vec.push(i);
-    }
-    assert_eq!(vec.iter().sum::<u64>(), (n-1)*n/2);
+    without_leaks(|| {
+        let n = 1000;
+        let mut vec = Vec::new();
+        for i in 0..n {
+            vec.push(i);
+        }
+        assert_eq!(vec.iter().sum::<u64>(), (n-1)*n/2);
+    });
 }
 
 // create ten thousand allocations after each other
 // This test ensures that the allocator reuses freed memory for subsequent allocations since it would run out of memory otherwise.
 #[test_case]
 fn many_boxes() {
-    for i in 0..HEAP_SIZE {
-        let x = Box::new(i);
-        assert_eq!(*x, i);
-    }
+    without_leaks(|| {
+        for i in 0..HEAP_SIZE {
+            let x = Box::new(i);
+            assert_eq!(*x, i);
+        }
+    });
 }
 
 // The main limitation of a bump allocator is that it can only reuse deallocated memory after all allocations have been freed. This means that a single long-lived allocation suffices to prevent memory reuse. 
+// Since this is expected, the test is skipped for the bump allocator.
+#[cfg(not(feature = "alloc-bump"))]
 #[test_case]
 fn many_boxes_long_lived() {
-    let long_lived = Box::new(1); // new
-    for i in 0..HEAP_SIZE {
-        let x = Box::new(i);
-        assert_eq!(*x, i);
+    without_leaks(|| {
+        let long_lived = Box::new(1); // new
+        for i in 0..HEAP_SIZE {
+            let x = Box::new(i);
+            assert_eq!(*x, i);
+        }
+        assert_eq!(*long_lived, 1);            // new
+    });
+}
+
+// allocate more memory than the heap has at boot, which is only possible if the heap grows
+// Only the fixed-size block allocator maps more pages on demand.
+#[cfg(feature = "alloc-fixed-block")]
+#[test_case]
+fn heap_grows_on_demand() {
+    without_leaks(|| {
+        let n = 4 * HEAP_SIZE;
+        let mut vec = Vec::with_capacity(n);
+        for i in 0..n {
+            vec.push(i as u8);
+        }
+        assert_eq!(vec.len(), n);
+        assert_eq!(vec[n - 1], (n - 1) as u8);
+    });
+}
+
+// the statistics follow allocations and deallocations
+#[test_case]
+fn stats_track_allocations() {
+    use blog_os::allocator;
+
+    let before = allocator::stats();
+    let value = Box::new([0u8; 100]);
+    let during = allocator::stats();
+    assert_eq!(during.allocated_bytes, before.allocated_bytes + 100);
+    assert!(during.peak_bytes >= during.allocated_bytes);
+    drop(value);
+    let after = allocator::stats();
+    assert_eq!(after.allocated_bytes, before.allocated_bytes);
+    assert_eq!(after.failed_allocations, before.failed_allocations);
+    serial_println!("\n{}", after);
+}
+
+// the allocator lock disables interrupts while it is held and restores the previous state afterwards
+#[test_case]
+fn lock_restores_interrupt_state() {
+    use blog_os::allocator::Locked;
+    use x86_64::instructions::interrupts;
+
+    let locked = Locked::new(0);
+    interrupts::without_interrupts(|| {
+        let _guard = locked.lock();
+        assert!(!interrupts::are_enabled());
+    });
+    assert!(interrupts::are_enabled());
+    {
+        let _guard = locked.lock();
+        assert!(!interrupts::are_enabled());
     }
-    assert_eq!(*long_lived, 1);            // new
-}
\ No newline at end of file
+    assert!(interrupts::are_enabled());
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    buffer_overrun();
+    serial_println!("[corruption not detected]");
+    exit_qemu(QemuExitCode::Failed);
+    blog_os::hlt_loop();
+}
+
+// write one byte past the end of an allocation, which must be detected when it is freed
+fn buffer_overrun() {
+    serial_print!("heap_corruption::buffer_overrun...\t");
+    let value = Box::new([0u8; 8]);
+    let ptr = Box::into_raw(value) as *mut u8;
+    unsafe {
+        ptr.add(8).write_volatile(0x42);
+        drop(Box::from_raw(ptr as *mut [u8; 8]));
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::expect_panic_message(info, "heap corruption: red zone 0 bytes after allocation")
+}
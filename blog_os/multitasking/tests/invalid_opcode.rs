@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use core::panic::PanicInfo;
+
+const EXPECTED: &str = "EXCEPTION: INVALID OPCODE\nRAX=";
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("invalid_opcode::invalid_opcode...\t");
+    blog_os::init();
+
+    unsafe { core::arch::asm!("ud2") };
+
+    serial_println!("[execution continued after invalid opcode]");
+    exit_qemu(QemuExitCode::Failed);
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::expect_panic_message(info, EXPECTED)
+}
@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use blog_os::allocator::{linked_list::LinkedListAllocator, Locked};
+
+// The allocator under test manages its own static region, so the tests work no matter which allocator is the global one.
+const HEAP_SIZE: usize = 64 * 1024;
+
+#[repr(C, align(4096))]
+struct Heap([u8; HEAP_SIZE]);
+
+static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    unsafe {
+        ALLOCATOR.lock().init(core::ptr::addr_of_mut!(HEAP) as usize, HEAP_SIZE);
+    }
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// Like `many_boxes_long_lived` in heap_allocation.rs, but the heap is filled with small blocks before they are freed again.
+// Without merging adjacent free regions, the heap would consist of small slivers afterwards and the large allocation would fail.
+#[test_case]
+fn many_boxes_long_lived_then_large() {
+    let small = Layout::from_size_align(32, 8).unwrap();
+    let large = Layout::from_size_align(HEAP_SIZE / 2, 8).unwrap();
+    const COUNT: usize = HEAP_SIZE / 2 / 32;
+
+    unsafe {
+        let long_lived = ALLOCATOR.alloc(small);
+        assert!(!long_lived.is_null());
+        for _ in 0..10 {
+            let mut boxes = [core::ptr::null_mut(); COUNT];
+            for slot in boxes.iter_mut() {
+                *slot = ALLOCATOR.alloc(small);
+                assert!(!slot.is_null());
+            }
+            for &ptr in boxes.iter() {
+                ALLOCATOR.dealloc(ptr, small);
+            }
+
+            let ptr = ALLOCATOR.alloc(large);
+            assert!(!ptr.is_null(), "free regions were not merged");
+            ALLOCATOR.dealloc(ptr, large);
+        }
+        ALLOCATOR.dealloc(long_lived, small);
+    }
+}
+
+// allocations with large alignments must not leak the padding in front of them
+#[test_case]
+fn aligned_allocations_are_recovered() {
+    let aligned = Layout::from_size_align(64, 1024).unwrap();
+    let whole = Layout::from_size_align(HEAP_SIZE, 8).unwrap();
+
+    unsafe {
+        let offset = ALLOCATOR.alloc(Layout::from_size_align(16, 8).unwrap());
+        for _ in 0..1000 {
+            let ptr = ALLOCATOR.alloc(aligned);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 1024, 0);
+            ALLOCATOR.dealloc(ptr, aligned);
+        }
+        ALLOCATOR.dealloc(offset, Layout::from_size_align(16, 8).unwrap());
+
+        // the whole heap is a single free region again
+        let ptr = ALLOCATOR.alloc(whole);
+        assert!(!ptr.is_null());
+        ALLOCATOR.dealloc(ptr, whole);
+    }
+}
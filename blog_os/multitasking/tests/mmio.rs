@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{self, mmio::MMIO_FLAGS};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::PhysAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// the registers of the local APIC are a device that every test machine has
+const LOCAL_APIC_VERSION: usize = 0x30;
+
+fn local_apic_address() -> PhysAddr {
+    let apic_base = unsafe { Msr::new(0x1b).read() };
+    PhysAddr::new(apic_base & 0x000f_ffff_ffff_f000)
+}
+
+#[test_case]
+fn reads_device_registers() {
+    let mmio = unsafe { memory::map_mmio(local_apic_address(), 0x400) }.unwrap();
+    assert_eq!(mmio.phys_addr(), local_apic_address());
+    assert_eq!(mmio.size(), 0x400);
+    // the version is in the low byte, the number of LVT entries minus one in the third
+    let version: u32 = mmio.read(LOCAL_APIC_VERSION);
+    assert_ne!(version & 0xff, 0);
+    assert!((version >> 16) & 0xff >= 3);
+}
+
+#[test_case]
+fn uncached_and_not_executable() {
+    let mmio = unsafe { memory::map_mmio(local_apic_address(), 0x400) }.unwrap();
+    let flags = memory::with_global(|mapper, _| match mapper.translate(mmio.virt_addr()) {
+        TranslateResult::Mapped { flags, .. } => Some(flags),
+        _ => None,
+    });
+    let flags = flags.unwrap().unwrap();
+    assert!(flags.contains(MMIO_FLAGS | PageTableFlags::PRESENT));
+    assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn unaligned_address() {
+    let whole = unsafe { memory::map_mmio(local_apic_address(), 0x400) }.unwrap();
+    let version = unsafe { memory::map_mmio(local_apic_address() + LOCAL_APIC_VERSION, 4) }.unwrap();
+    assert_eq!(version.virt_addr().as_u64() % 4096, LOCAL_APIC_VERSION as u64);
+    assert_eq!(version.read::<u32>(0), whole.read::<u32>(LOCAL_APIC_VERSION));
+}
+
+#[test_case]
+fn unmapped_on_drop() {
+    let mmio = unsafe { memory::map_mmio(local_apic_address(), 0x400) }.unwrap();
+    let addr = mmio.virt_addr();
+    assert!(memory::is_mapped(addr));
+    drop(mmio);
+    assert!(!memory::is_mapped(addr));
+    assert_eq!(memory::region::find(addr), None);
+}
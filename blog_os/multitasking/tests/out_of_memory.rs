@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![feature(allocator_api)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use blog_os::allocator::{self, HEAP_SIZE};
+use blog_os::task::executor::Executor;
+use bootloader::{entry_point, BootInfo};
+use core::mem;
+use core::panic::PanicInfo;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use blog_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    // `memory::init_global` is not called, so the heap cannot grow and stays at HEAP_SIZE
+    allocator::register_oom_handler(drop_cache).expect("failed to register the OOM handler");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// memory that the OOM handler can free, like a cache of some subsystem
+static CACHE: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+static HANDLER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn drop_cache(_layout: Layout) -> usize {
+    HANDLER_CALLS.fetch_add(1, Ordering::Relaxed);
+    match CACHE.lock().take() {
+        Some(cache) => cache.capacity(),
+        None => 0,
+    }
+}
+
+#[test_case]
+fn failed_allocation_returns_error() {
+    let mut vec: Vec<u8> = Vec::new();
+    assert!(vec.try_reserve_exact(HEAP_SIZE * 2).is_err());
+    assert!(Box::<[u8; HEAP_SIZE * 2]>::try_new_uninit().is_err());
+    // the heap is still usable afterwards
+    let value = Box::new(42);
+    assert_eq!(*value, 42);
+}
+
+#[test_case]
+fn oom_handler_frees_cache() {
+    *CACHE.lock() = Some(Vec::with_capacity(HEAP_SIZE / 2));
+    let calls = HANDLER_CALLS.load(Ordering::Relaxed);
+
+    // only fits if the cache is dropped
+    let mut vec: Vec<u8> = Vec::new();
+    assert!(vec.try_reserve_exact(HEAP_SIZE * 3 / 4).is_ok());
+    assert!(CACHE.lock().is_none());
+    assert_eq!(HANDLER_CALLS.load(Ordering::Relaxed), calls + 1);
+}
+
+// a block that is kept allocated to fill the heap, linked to the block allocated before it
+struct Block {
+    previous: Option<NonNull<Block>>,
+    layout: Layout,
+}
+
+/// Allocates blocks of decreasing size until not even the smallest one fits anymore.
+fn fill_heap() -> Option<NonNull<Block>> {
+    let mut last = None;
+    let mut size = HEAP_SIZE;
+    while size >= mem::size_of::<Block>() {
+        let layout = Layout::from_size_align(size, mem::align_of::<Block>()).unwrap();
+        match NonNull::new(unsafe { alloc::alloc::alloc(layout) }) {
+            Some(ptr) => {
+                let block = ptr.cast::<Block>();
+                unsafe { block.as_ptr().write(Block { previous: last, layout }) };
+                last = Some(block);
+            }
+            None => size /= 2,
+        }
+    }
+    last
+}
+
+/// Frees the blocks of `fill_heap`.
+fn free_heap(mut last: Option<NonNull<Block>>) {
+    while let Some(block) = last {
+        let Block { previous, layout } = unsafe { block.as_ptr().read() };
+        unsafe { alloc::alloc::dealloc(block.as_ptr().cast(), layout) };
+        last = previous;
+    }
+}
+
+#[test_case]
+fn executor_setup_reports_oom() {
+    let blocks = fill_heap();
+    assert!(Executor::try_new().is_err());
+    free_heap(blocks);
+    assert!(Executor::try_new().is_ok());
+}
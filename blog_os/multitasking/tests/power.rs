@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::{acpi, power};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { acpi::init(phys_mem_offset) }.expect("ACPI initialization failed");
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// shutdown and reboot end QEMU without an exit code, so only the lookup of the sleep state is tested
+#[test_case]
+fn s5_sleep_type_in_dsdt() {
+    // QEMU declares `Name(_S5, Package() { 0, 0, 0, 0 })`
+    assert_eq!(power::s5_sleep_type(), Some((0, 0)));
+}
+
+// `Name(_S5_, Package() { 5, 5, 0, 0 })` with a one-byte package length and byte constants
+const S5_BYTES: [u8; 14] = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0a, 0x05, 0x0a, 0x05, 0x00, 0x00];
+
+#[test_case]
+fn find_s5_byte_constants() {
+    assert_eq!(power::find_s5(&S5_BYTES), Some((5, 5)));
+
+    // other code in front of the object is skipped
+    let mut aml = [0u8; 17];
+    aml[..3].copy_from_slice(&[0x10, 0x05, b'_']);
+    aml[3..].copy_from_slice(&S5_BYTES);
+    assert_eq!(power::find_s5(&aml), Some((5, 5)));
+}
+
+#[test_case]
+fn find_s5_word_and_dword_constants() {
+    // Package() { 0x0107, 0x00000006 }: only the low three bits are a sleep type
+    let aml = [
+        0x08, b'_', b'S', b'5', b'_', 0x12, 0x0a, 0x02, 0x0b, 0x07, 0x01, 0x0c, 0x06, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(power::find_s5(&aml), Some((7, 6)));
+
+    // Zero and One are opcodes without a prefix
+    let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x04, 0x02, 0x00, 0x01];
+    assert_eq!(power::find_s5(&aml), Some((0, 1)));
+}
+
+#[test_case]
+fn find_s5_root_name() {
+    let aml = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x06, 0x02, 0x0a, 0x03, 0x0a, 0x04];
+    assert_eq!(power::find_s5(&aml), Some((3, 4)));
+}
+
+#[test_case]
+fn find_s5_multi_byte_package_length() {
+    // bits 6 and 7 of the first byte are the number of bytes that follow it
+    let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x47, 0x00, 0x02, 0x0a, 0x02, 0x0a, 0x01];
+    assert_eq!(power::find_s5(&aml), Some((2, 1)));
+    let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x88, 0x00, 0x00, 0x02, 0x0a, 0x02, 0x0a, 0x01];
+    assert_eq!(power::find_s5(&aml), Some((2, 1)));
+}
+
+#[test_case]
+fn find_s5_truncated() {
+    // cut off anywhere after the name, the object is incomplete
+    for len in 4..S5_BYTES.len() - 2 {
+        assert_eq!(power::find_s5(&S5_BYTES[..len]), None);
+    }
+    // a DWord that is cut off
+    let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x0a, 0x02, 0x0a, 0x05, 0x0c, 0x05, 0x00];
+    assert_eq!(power::find_s5(&aml), None);
+    // the name alone, without NameOp or a package
+    assert_eq!(power::find_s5(&S5_BYTES[1..]), None);
+    assert_eq!(power::find_s5(&[0x08, b'_', b'S', b'5', b'_', 0x0a, 0x05]), None);
+    assert_eq!(power::find_s5(&[]), None);
+}
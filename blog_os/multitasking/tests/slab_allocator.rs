@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use alloc::vec::Vec;
+use blog_os::allocator::slab::SlabCache;
+use blog_os::task::Task;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// a 48-byte object, which the fixed-size block allocator would round up to 64 bytes
+struct Object {
+    data: [u64; 6],
+}
+
+static OBJECT_CACHE: SlabCache<Object> = SlabCache::new("object");
+
+#[test_case]
+fn simple_allocation() {
+    let object = OBJECT_CACHE.alloc(Object { data: [7; 6] }).ok().unwrap();
+    assert_eq!(object.data, [7; 6]);
+    assert_eq!(OBJECT_CACHE.name(), "object");
+    assert_eq!(OBJECT_CACHE.stats().objects_in_use, 1);
+}
+
+// objects are packed densely and all slabs but one are released once the objects are gone
+#[test_case]
+fn empty_slabs_are_released() {
+    let objects_per_slab = OBJECT_CACHE.stats().objects_per_slab;
+    assert!(objects_per_slab >= 4096 / 48 - 1);
+
+    let n = 10 * objects_per_slab;
+    let mut objects = Vec::new();
+    for i in 0..n {
+        let object = OBJECT_CACHE.alloc(Object { data: [i as u64; 6] }).ok().unwrap();
+        objects.push(object);
+    }
+    assert_eq!(OBJECT_CACHE.stats().slabs, 10);
+    for (i, object) in objects.iter().enumerate() {
+        assert_eq!(object.data[5], i as u64);
+    }
+
+    drop(objects);
+    assert_eq!(OBJECT_CACHE.stats().slabs, 1);
+    assert_eq!(OBJECT_CACHE.stats().objects_in_use, 0);
+}
+
+// freeing the last object keeps its slab, so the next allocation does not need a new one
+#[test_case]
+fn one_empty_slab_is_kept() {
+    static CACHE: SlabCache<Object> = SlabCache::new("kept");
+    for i in 0..3 {
+        let object = CACHE.alloc(Object { data: [i; 6] }).ok().unwrap();
+        assert_eq!(CACHE.stats().slabs, 1);
+        drop(object);
+        assert_eq!(CACHE.stats().slabs, 1);
+        assert_eq!(CACHE.stats().objects_in_use, 0);
+    }
+}
+
+// full and partial slabs are tracked separately, and the occupancy of every slab is available
+#[test_case]
+fn per_slab_occupancy() {
+    static CACHE: SlabCache<Object> = SlabCache::new("occupancy");
+    let objects_per_slab = CACHE.stats().objects_per_slab;
+    let mut objects: Vec<_> = (0..objects_per_slab + 3)
+        .map(|i| CACHE.alloc(Object { data: [i as u64; 6] }).ok().unwrap())
+        .collect();
+    let stats = CACHE.stats();
+    assert_eq!((stats.full_slabs, stats.partial_slabs, stats.empty_slabs), (1, 1, 0));
+    let mut occupancy = [0; 2];
+    let mut slabs = 0;
+    CACHE.for_each_slab(|in_use| {
+        occupancy[slabs] = in_use;
+        slabs += 1;
+    });
+    // the full slabs come first
+    assert_eq!(occupancy, [objects_per_slab, 3]);
+
+    // freeing an object of the full slab makes it partial
+    drop(objects.remove(0));
+    let stats = CACHE.stats();
+    assert_eq!((stats.full_slabs, stats.partial_slabs, stats.empty_slabs), (0, 2, 0));
+
+    drop(objects);
+    let stats = CACHE.stats();
+    assert_eq!((stats.slabs, stats.partial_slabs, stats.empty_slabs), (1, 0, 1));
+}
+
+// the tasks of the executor are not `Sync`, but a static cache for them is fine
+static TASK_CACHE: SlabCache<Task> = SlabCache::new("task");
+
+#[test_case]
+fn task_cache() {
+    let task = TASK_CACHE.alloc(Task::new(async {})).ok().unwrap();
+    assert_eq!(TASK_CACHE.stats().objects_in_use, 1);
+    drop(task);
+    assert_eq!(TASK_CACHE.stats().objects_in_use, 0);
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::symbols;
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start() -> ! {
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[inline(never)]
+fn marker() -> u64 {
+    42
+}
+
+// the table is embedded by runner.sh
+#[test_case]
+fn table_is_embedded() {
+    assert!(symbols::is_available());
+}
+
+#[test_case]
+fn lookup_function() {
+    let address = marker as fn() -> u64 as usize as u64;
+    let symbol = symbols::lookup(VirtAddr::new(address + 1)).expect("marker not found");
+    assert_eq!(symbol.name, "symbols::marker");
+    assert_eq!(symbol.address.as_u64(), address);
+    assert_eq!(symbol.offset, 1);
+    assert_eq!(marker(), 42);
+}
+
+#[test_case]
+fn lookup_outside_kernel() {
+    assert_eq!(symbols::lookup(VirtAddr::new(0x1000)), None);
+}
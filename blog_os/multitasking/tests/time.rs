@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::{pit, time::{self, Instant}};
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    blog_os::init();
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn wait_for_ticks(count: u64) {
+    let start = time::ticks();
+    while time::ticks() < start + count {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn pit_runs_at_tick_rate() {
+    assert_eq!(pit::frequency(), time::TICK_RATE);
+    // 1193182 Hz / 1193
+    assert_eq!(pit::period_nanos(), 999_847);
+}
+
+#[test_case]
+fn clock_is_monotonic() {
+    let mut last = Instant::now();
+    for _ in 0..100_000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+// within a single tick, the TSC interpolation still lets the clock advance
+#[test_case]
+fn clock_has_sub_tick_precision() {
+    wait_for_ticks(3);
+    let tick = time::ticks();
+    let first = Instant::now();
+    let mut later = first;
+    while time::ticks() == tick && later == first {
+        later = Instant::now();
+    }
+    assert!(later > first);
+    assert!(later - first < Duration::from_nanos(pit::period_nanos()));
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+// the clock keeps running at the right speed when the tick rate changes, measured against the TSC
+#[test_case]
+fn change_tick_rate() {
+    // the TSC cycles of 200 ticks at `TICK_RATE`
+    wait_for_ticks(1);
+    let start = rdtsc();
+    wait_for_ticks(200);
+    let calibration_tsc = rdtsc() - start;
+    let calibration_nanos = 200 * pit::period_nanos();
+    let tsc_to_nanos = |tsc: u64| (u128::from(tsc) * u128::from(calibration_nanos) / u128::from(calibration_tsc)) as u64;
+
+    for &rate in &[100, time::TICK_RATE] {
+        // change the rate right after a tick, so that almost the complete period in progress is still ahead
+        wait_for_ticks(1);
+        let (before, before_tsc) = (Instant::now(), rdtsc());
+        assert_eq!(time::set_tick_rate(rate), rate);
+        wait_for_ticks(5);
+        let elapsed = before.elapsed().as_nanos() as u64;
+        let expected = tsc_to_nanos(rdtsc() - before_tsc);
+        let error = elapsed.max(expected) - elapsed.min(expected);
+        assert!(error < 2_000_000, "clock elapsed {} ns, TSC elapsed {} ns", elapsed, expected);
+    }
+}
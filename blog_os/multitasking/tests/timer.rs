@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::task::{simple_executor::SimpleExecutor, timer, Task};
+use blog_os::time::Instant;
+use bootloader::{entry_point, BootInfo};
+use core::future::{self, Future};
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// runs the future to completion; the SimpleExecutor polls in a busy loop, so no timer needs to wake it
+fn block_on(future: impl Future<Output = ()> + 'static) {
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(future));
+    executor.run();
+}
+
+#[test_case]
+fn sleep_waits_for_duration() {
+    let duration = Duration::from_millis(200);
+    let start = Instant::now();
+    block_on(async move { timer::sleep(duration).await });
+    assert!(start.elapsed() >= duration);
+}
+
+#[test_case]
+fn timeout_elapses() {
+    block_on(async {
+        let result = timer::timeout(Duration::from_millis(100), future::pending::<()>()).await;
+        assert_eq!(result, Err(timer::Elapsed));
+    });
+}
+
+#[test_case]
+fn timeout_returns_output() {
+    block_on(async {
+        let result = timer::timeout(Duration::from_secs(10), async { 42 }).await;
+        assert_eq!(result, Ok(42));
+    });
+}
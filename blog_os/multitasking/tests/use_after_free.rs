@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use blog_os::allocator::debug;
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    use_after_free();
+    serial_println!("[corruption not detected]");
+    exit_qemu(QemuExitCode::Failed);
+    blog_os::hlt_loop();
+}
+
+// write through a dangling pointer, which must be detected while the allocation is in the quarantine
+fn use_after_free() {
+    serial_print!("use_after_free::use_after_free...\t");
+    let ptr = Box::into_raw(Box::new([0u8; 32])) as *mut u8;
+    unsafe {
+        drop(Box::from_raw(ptr as *mut [u8; 32]));
+        ptr.add(4).write_volatile(0x42);
+    }
+    debug::check_heap();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::expect_panic_message(info, "heap corruption: write to freed allocation")
+}
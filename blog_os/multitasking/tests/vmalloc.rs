@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{self, region::{self, Backing, RegionError}};
+use blog_os::memory::vmalloc::{self, VmallocError, VMALLOC_START, VMALLOC_SIZE};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::ptr;
+use x86_64::structures::paging::{PageTableFlags, Translate};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::test_init(boot_info);
+
+    test_main();
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::WRITABLE;
+
+fn free_frames() -> usize {
+    memory::with_global(|_, frame_allocator| frame_allocator.free_frames()).unwrap()
+}
+
+#[test_case]
+fn mapped_and_writable() {
+    let region = vmalloc::vmalloc(3 * 4096, FLAGS).unwrap();
+    assert!(vmalloc::is_vmalloc_address(region.start));
+    assert_eq!(region.len, 3 * 4096);
+    assert_eq!(region.backing, Backing::Anonymous);
+
+    for page in region.pages() {
+        assert!(memory::is_mapped(page.start_address()));
+        let ptr = page.start_address().as_mut_ptr::<u64>();
+        unsafe { ptr::write_volatile(ptr, page.start_address().as_u64()) };
+    }
+    for page in region.pages() {
+        let ptr = page.start_address().as_ptr::<u64>();
+        assert_eq!(unsafe { ptr::read_volatile(ptr) }, page.start_address().as_u64());
+    }
+    assert_eq!(vmalloc::vfree(region.start), Some(region));
+}
+
+#[test_case]
+fn size_rounded_up() {
+    let region = vmalloc::vmalloc(1, FLAGS).unwrap();
+    assert_eq!(region.len, 4096);
+    vmalloc::vfree(region.start).unwrap();
+
+    assert!(matches!(
+        vmalloc::vmalloc(0, FLAGS),
+        Err(VmallocError::Region(RegionError::Unaligned))
+    ));
+    assert!(matches!(
+        vmalloc::vmalloc(VMALLOC_SIZE + 1, FLAGS),
+        Err(VmallocError::Region(RegionError::NoSpace))
+    ));
+}
+
+#[test_case]
+fn ranges_do_not_overlap() {
+    let a = vmalloc::vmalloc(2 * 4096, FLAGS).unwrap();
+    let b = vmalloc::vmalloc(4096, FLAGS).unwrap();
+    let c = vmalloc::vmalloc(4096, FLAGS).unwrap();
+    // every range is followed by an unmapped guard page
+    assert!(b.start >= a.end() + 4096u64 || a.start >= b.end() + 4096u64);
+    assert!(c.start >= b.end() + 4096u64 || b.start >= c.end() + 4096u64);
+    assert!(!memory::is_mapped(a.end()));
+
+    // the space of a freed range is reused
+    vmalloc::vfree(a.start).unwrap();
+    let d = vmalloc::vmalloc(4096, FLAGS).unwrap();
+    assert_eq!(d.start, a.start);
+
+    for region in &[b, c, d] {
+        vmalloc::vfree(region.start).unwrap();
+    }
+}
+
+#[test_case]
+fn frames_not_contiguous_and_freed() {
+    // a first allocation creates the page tables, which stay
+    let region = vmalloc::vmalloc(8 * 4096, FLAGS).unwrap();
+    vmalloc::vfree(region.start).unwrap();
+
+    let free = free_frames();
+    let region = vmalloc::vmalloc(8 * 4096, FLAGS).unwrap();
+    assert_eq!(free_frames(), free - 8);
+    let translated = memory::with_global(|mapper, _| {
+        region.pages().all(|page| mapper.translate_addr(page.start_address()).is_some())
+    });
+    assert_eq!(translated, Some(true));
+
+    vmalloc::vfree(region.start).unwrap();
+    assert_eq!(free_frames(), free);
+    assert!(region.pages().all(|page| !memory::is_mapped(page.start_address())));
+}
+
+#[test_case]
+fn reserve_maps_on_demand() {
+    let region = vmalloc::reserve(2 * 4096, FLAGS, Backing::Anonymous).unwrap();
+    assert!(!memory::is_mapped(region.start));
+    assert_eq!(unsafe { ptr::read_volatile(region.start.as_ptr::<u64>()) }, 0);
+    assert!(memory::is_mapped(region.start));
+    assert_eq!(region::find(region.start + 4096u64), Some(region));
+    vmalloc::vfree(region.start).unwrap();
+}
+
+#[test_case]
+fn vfree_only_frees_vmalloc_ranges() {
+    assert_eq!(vmalloc::vfree(VirtAddr::new(VMALLOC_START)), None);
+    let outside = region::Region {
+        start: VirtAddr::new(VMALLOC_START + VMALLOC_SIZE),
+        len: 4096,
+        flags: FLAGS,
+        backing: Backing::Anonymous,
+    };
+    region::register(outside).unwrap();
+    assert_eq!(vmalloc::vfree(outside.start), None);
+    assert_eq!(region::unregister(outside.start), Some(outside));
+}